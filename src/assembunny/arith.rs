@@ -0,0 +1,83 @@
+use std::fmt;
+use std::str::FromStr;
+use num_bigint::BigInt;
+
+// What `inc`, `dec` and the fused add/multiply do when a result doesn't fit
+// in an i64. `Checked` stops the machine with `Trap::Overflow` instead of
+// letting a wrong answer through. `Big` asks for registers that never
+// overflow: `RunConfig::run_big` runs the program on `CompiledVM<BigInt>`,
+// and a VM with i64 registers can only treat it as `Checked`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Arith {
+    Wrapping,
+    #[default]
+    Checked,
+    Big,
+}
+
+impl FromStr for Arith {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrapping" => Ok(Arith::Wrapping),
+            "checked" => Ok(Arith::Checked),
+            "big" => Ok(Arith::Big),
+            _ => Err(format!("unknown arithmetic `{s}`, expected wrapping, checked or big")),
+        }
+    }
+}
+
+// Why a VM stopped with `RunResult::Err`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Trap {
+    InvalidInstruction,
+    Overflow,
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::InvalidInstruction => write!(f, "invalid instruction"),
+            Trap::Overflow => write!(f, "arithmetic overflow"),
+        }
+    }
+}
+
+// A register value. `add` and `mul` return None when the result overflows
+// under `arith`; a type that can't overflow ignores it.
+pub trait Word: Clone + fmt::Debug + fmt::Display + Eq + Ord + From<i64> {
+    fn add(&self, rhs: &Self, arith: Arith) -> Option<Self>;
+    fn mul(&self, rhs: &Self, arith: Arith) -> Option<Self>;
+    // For jump and toggle offsets; None if out of i64 range.
+    fn to_i64(&self) -> Option<i64>;
+}
+
+impl Word for i64 {
+    fn add(&self, rhs: &Self, arith: Arith) -> Option<Self> {
+        match arith {
+            Arith::Wrapping => Some(self.wrapping_add(*rhs)),
+            Arith::Checked | Arith::Big => self.checked_add(*rhs),
+        }
+    }
+    fn mul(&self, rhs: &Self, arith: Arith) -> Option<Self> {
+        match arith {
+            Arith::Wrapping => Some(self.wrapping_mul(*rhs)),
+            Arith::Checked | Arith::Big => self.checked_mul(*rhs),
+        }
+    }
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self)
+    }
+}
+
+impl Word for BigInt {
+    fn add(&self, rhs: &Self, _: Arith) -> Option<Self> {
+        Some(self + rhs)
+    }
+    fn mul(&self, rhs: &Self, _: Arith) -> Option<Self> {
+        Some(self * rhs)
+    }
+    fn to_i64(&self) -> Option<i64> {
+        self.try_into().ok()
+    }
+}
@@ -0,0 +1,128 @@
+use std::error::Error;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::Path;
+use super::decompile::decompile;
+use super::headless::RunConfig;
+use super::instruction::{Dialect, Instruction, parse_program};
+use super::register::Reg;
+use super::symbolic::analyse;
+
+// Command-line handling shared by the assembunny days:
+//
+//   dayNN [options] [input-file]
+//
+//   --debug            open the interactive debugger
+//   --profile          print per-instruction counts after the run
+//   --decompile        print the program as pseudo-code instead of running it
+//   --symbolic <r,..>  print the final registers as formulas in the given
+//                      inputs (and any --set values) instead of running it
+//   --break <pc>       stop before executing <pc>
+//   --watch <cond>     stop once e.g. "a == 0" holds
+//   --max-steps <n>    stop after n instructions
+//   --set <r>=<n>      override a register before running
+//   --arith <mode>     wrapping, checked (the default) or big
+//   --script <file>    read any of the above from a file
+//
+// Without an input file, the program is read from stdin if it isn't a
+// terminal, otherwise from dayNN.input.
+fn parse_args(args: &[String]) -> Result<(Option<String>, RunConfig), String> {
+    let mut config = RunConfig::default();
+    let mut input = None;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str| iter.next()
+            .ok_or_else(|| format!("{flag} needs an argument"));
+        match arg.as_str() {
+            "--debug" => config.apply_command("debug")?,
+            "--profile" => config.apply_command("profile")?,
+            "--decompile" => { config.decompile = true; },
+            "--symbolic" => {
+                let inputs = value(arg)?.split(',')
+                    .map(|r| r.trim().parse::<Reg>().map_err(|_| format!("`{r}` is not a register")))
+                    .collect::<Result<_, _>>()?;
+                config.symbolic = Some(inputs);
+            },
+            "--break" => config.apply_command(&format!("break {}", value(arg)?))?,
+            "--watch" => config.apply_command(&format!("watch {}", value(arg)?))?,
+            "--max-steps" => config.apply_command(&format!("limit {}", value(arg)?))?,
+            "--set" => config.apply_command(&format!("set {}", value(arg)?))?,
+            "--arith" => config.apply_command(&format!("arith {}", value(arg)?))?,
+            "--script" => {
+                let path = value(arg)?;
+                let src = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+                config.apply_script(&src).map_err(|e| format!("{path}: {e}"))?;
+            },
+            flag if flag.starts_with("--") => { return Err(format!("unknown option {flag}")); },
+            _ if input.is_none() => { input = Some(arg.clone()); },
+            _ => { return Err(format!("unexpected argument {arg}")); },
+        }
+    }
+    Ok((input, config))
+}
+
+fn read_source(path: Option<&str>) -> io::Result<String> {
+    match path {
+        Some(path) => fs::read_to_string(path),
+        None if !io::stdin().is_terminal() => io::read_to_string(io::stdin()),
+        None => {
+            let exe = std::env::args().next().unwrap_or_default();
+            let stem = Path::new(&exe).file_stem().unwrap_or_default().to_string_lossy();
+            fs::read_to_string(format!("{stem}.input"))
+        },
+    }
+}
+
+// What a day binary should do with its arguments: run the program, or just
+// print something about it (for --decompile and --symbolic) and exit.
+#[derive(Debug)]
+pub enum Action {
+    Run(Vec<Instruction>, RunConfig),
+    Print(String),
+}
+
+fn action(program: Vec<Instruction>, config: RunConfig) -> Result<Action, Box<dyn Error>> {
+    if config.decompile {
+        return Ok(Action::Print(decompile(&program)));
+    }
+    if let Some(inputs) = &config.symbolic {
+        return Ok(Action::Print(analyse(&program, inputs, &config.registers)?.to_string()));
+    }
+    Ok(Action::Run(program, config))
+}
+
+pub fn load(dialect: Dialect) -> Result<Action, Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (input, config) = parse_args(&args)?;
+    let src = read_source(input.as_deref())?;
+    let program = parse_program(&src, dialect)?;
+    action(program, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_test() {
+        let args: Vec<String> = ["--break", "4", "input.txt", "--set", "a=12", "--debug"]
+            .iter().map(|s| s.to_string()).collect();
+        let (input, config) = parse_args(&args).unwrap();
+        assert_eq!(input.as_deref(), Some("input.txt"));
+        assert!(config.breakpoints.contains(&4));
+        assert_eq!(config.registers, vec![('a'.into(), 12)]);
+        assert!(config.debug);
+        assert_eq!(parse_args(&["--max-steps".to_string()]).unwrap_err(), "--max-steps needs an argument");
+        let (_, config) = parse_args(&["--symbolic".to_string(), "a,r3".to_string()]).unwrap();
+        assert_eq!(config.symbolic, Some(vec![Reg::Named('a'), Reg::Numbered(3)]));
+    }
+
+    #[test]
+    fn action_test() {
+        let program = parse_program("cpy 2 a\ninc a\n", Dialect::BASIC).unwrap();
+        let (_, config) = parse_args(&["--decompile".to_string()]).unwrap();
+        assert!(matches!(action(program.clone(), config).unwrap(), Action::Print(text) if text == "a = 2\na += 1\n"));
+        let (_, config) = parse_args(&["--max-steps".to_string(), "5".to_string()]).unwrap();
+        assert!(matches!(action(program, config).unwrap(), Action::Run(_, config) if config.max_steps == Some(5)));
+    }
+}
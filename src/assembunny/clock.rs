@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use ya_advent_lib::vm_shell::RunResult;
+use super::cpu::AssembunnyVM;
+use super::instruction::{Dialect, Instruction};
+use super::state::{LoopDetector, MachineState};
+
+// Runs the program with the given initial `a` and reports whether it emits
+// 0, 1, 0, 1, ... forever. "Forever" is established by reaching the same
+// machine state at two outputs that expect the same next value: from there
+// the output can only repeat. A state repeating between two outputs means
+// the program has gone quiet for good.
+pub fn is_clock_signal(program: &[Instruction], a: i64) -> bool {
+    let mut vm = AssembunnyVM::new(program, Dialect::FULL);
+    vm.optimise();
+    vm.set_reg('a', a);
+    let mut expect = 0;
+    let mut at_output: HashSet<(MachineState, i64)> = HashSet::new();
+    let mut quiet = LoopDetector::new();
+    loop {
+        if vm.step() != RunResult::Ok {
+            return false;
+        }
+        let out = vm.take_output();
+        if out.is_empty() {
+            if quiet.check(&vm.shell.vm) {
+                return false;
+            }
+            continue;
+        }
+        for v in out {
+            if v != expect {
+                return false;
+            }
+            expect = 1 - expect;
+        }
+        quiet.reset();
+        if !at_output.insert((vm.state(), expect)) {
+            return true;
+        }
+    }
+}
+
+pub fn find_clock_signal(program: &[Instruction], candidates: Range<i64>) -> Option<i64> {
+    candidates.into_iter().find(|a| is_clock_signal(program, *a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::parse_program;
+    use crate::assembunny::test_programs::DAY25;
+
+    #[test]
+    fn clock_test() {
+        let program = parse_program(DAY25, Dialect::FULL).unwrap();
+        assert!(!is_clock_signal(&program, 1));
+        assert!(is_clock_signal(&program, 198));
+        assert_eq!(find_clock_signal(&program, 190..1000), Some(198));
+    }
+}
@@ -0,0 +1,324 @@
+use num_bigint::BigInt;
+use ya_advent_lib::vm_shell::RunResult;
+use super::arith::{Arith, Trap, Word};
+use super::instruction::{Dialect, Instruction, RI};
+use super::optimise::{optimise, fuse_around, unfuse_around};
+use super::register::{Reg, REGISTER_FILE_LEN};
+
+// Operands with registers resolved to indices into the register file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Operand {
+    Reg(usize),
+    Imm(i64),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Op {
+    Cpy(Operand, usize),
+    Inc(usize),
+    Dec(usize),
+    Jnz(Operand, Operand),
+    Tgl(Operand),
+    Out(usize),
+    Add(usize, usize),
+    Mul(usize, Operand, usize, usize),
+    Nop,
+    Invalid,
+}
+
+fn reg(r: Reg) -> usize {
+    r.index()
+}
+
+fn operand(ri: RI) -> Operand {
+    match ri {
+        RI::Reg(r) => Operand::Reg(reg(r)),
+        RI::Imm(i) => Operand::Imm(i),
+    }
+}
+
+fn compile(i: &Instruction, dialect: Dialect) -> Op {
+    if !dialect.allows(i) {
+        return Op::Invalid;
+    }
+    match i {
+        Instruction::Cpy(x, y) => Op::Cpy(operand(*x), reg(*y)),
+        Instruction::Inc(x) => Op::Inc(reg(*x)),
+        Instruction::Dec(x) => Op::Dec(reg(*x)),
+        Instruction::Jnz(x, y) => Op::Jnz(operand(*x), operand(*y)),
+        Instruction::Tgl(x) => Op::Tgl(operand(*x)),
+        Instruction::Out(x) => Op::Out(reg(*x)),
+        Instruction::Add(dst, src, _) => Op::Add(reg(*dst), reg(*src)),
+        Instruction::Mul(dst, factor, inner, outer, _) =>
+            Op::Mul(reg(*dst), operand(*factor), reg(*inner), reg(*outer)),
+        Instruction::InvCpy(..) | Instruction::InvInc(_) | Instruction::InvDec(_) => Op::Nop,
+    }
+}
+
+// Jump and toggle offsets too large for an i64 are clamped, which still
+// lands them past either end of the program.
+fn offset<W: Word>(w: &W) -> i64 {
+    w.to_i64().unwrap_or(if *w > W::from(0) { i64::MAX } else { i64::MIN })
+}
+
+enum Next {
+    Ok,
+    Jump(i64),
+    Err(Trap),
+}
+
+// An alternative to AssembunnyVM that translates the program into ops with
+// register indices up front, so each step is a single match on a compact op
+// and array accesses instead of HashMap lookups. `program` stays the source
+// of truth for `tgl`; when it rewrites a slot, only the slots that changed
+// are recompiled. Register values are i64 by default; `CompiledVM::big`
+// makes a VM whose registers are arbitrary-precision and never overflow.
+pub struct CompiledVM<W: Word = i64> {
+    pub program: Vec<Instruction>,
+    pub registers: Vec<W>,
+    pub pc: usize,
+    pub steps: usize,
+    pub output: Vec<W>,
+    pub arith: Arith,
+    pub trap: Option<Trap>,
+    code: Vec<Op>,
+    dialect: Dialect,
+    optimise: bool,
+}
+
+impl CompiledVM {
+    pub fn new(program: &[Instruction], dialect: Dialect) -> Self {
+        Self::build(program, dialect)
+    }
+}
+
+impl CompiledVM<BigInt> {
+    pub fn big(program: &[Instruction], dialect: Dialect) -> Self {
+        Self::build(program, dialect)
+    }
+}
+
+impl<W: Word> CompiledVM<W> {
+    fn build(program: &[Instruction], dialect: Dialect) -> Self {
+        Self {
+            program: program.to_vec(),
+            registers: vec![W::from(0); REGISTER_FILE_LEN],
+            pc: 0,
+            steps: 0,
+            output: Vec::new(),
+            arith: Arith::default(),
+            trap: None,
+            code: program.iter().map(|i| compile(i, dialect)).collect(),
+            dialect,
+            optimise: false,
+        }
+    }
+    pub fn get_reg(&self, r: impl Into<Reg>) -> W {
+        self.registers[reg(r.into())].clone()
+    }
+    pub fn set_reg(&mut self, r: impl Into<Reg>, v: impl Into<W>) {
+        self.registers[reg(r.into())] = v.into();
+    }
+    pub fn optimise(&mut self) {
+        self.program = optimise(&self.program);
+        self.code = self.program.iter().map(|i| compile(i, self.dialect)).collect();
+        self.optimise = true;
+    }
+    pub fn take_output(&mut self) -> Vec<W> {
+        std::mem::take(&mut self.output)
+    }
+    // Nonzero registers in name order, as in MachineState.
+    pub fn registers(&self) -> Vec<(Reg, W)> {
+        let zero = W::from(0);
+        self.registers.iter()
+            .enumerate()
+            .filter(|(_, v)| **v != zero)
+            .map(|(r, v)| (Reg::from_index(r), v.clone()))
+            .collect()
+    }
+
+    fn value(&self, o: Operand) -> W {
+        match o {
+            Operand::Reg(r) => self.registers[r].clone(),
+            Operand::Imm(i) => W::from(i),
+        }
+    }
+
+    fn toggle(&mut self, loc: usize) {
+        let start = loc.saturating_sub(5);
+        let before = self.program[start..=loc].to_vec();
+        unfuse_around(&mut self.program, loc);
+        self.program[loc] = self.program[loc].toggled();
+        if self.optimise {
+            fuse_around(&mut self.program, loc);
+        }
+        for (idx, old) in before.iter().enumerate() {
+            if self.program[start + idx] != *old {
+                self.code[start + idx] = compile(&self.program[start + idx], self.dialect);
+            }
+        }
+    }
+
+    // The loop a fused op stands for has to run the slow way when its
+    // counters aren't positive; that starts with the slot's original head.
+    fn fallback(&mut self) -> Next {
+        let orig = compile(self.program[self.pc].original(), self.dialect);
+        self.execute(orig)
+    }
+
+    // Writes `dst + n` to `dst`, unless that overflows.
+    fn add_to(&mut self, dst: usize, n: &W) -> Option<()> {
+        self.registers[dst] = self.registers[dst].add(n, self.arith)?;
+        Some(())
+    }
+
+    fn execute(&mut self, op: Op) -> Next {
+        let zero = W::from(0);
+        let done = match op {
+            Op::Cpy(x, y) => { self.registers[y] = self.value(x); Some(()) },
+            Op::Inc(x) => self.add_to(x, &W::from(1)),
+            Op::Dec(x) => self.add_to(x, &W::from(-1)),
+            Op::Jnz(x, y) if self.value(x) != zero => { return Next::Jump(offset(&self.value(y))); },
+            Op::Jnz(..) | Op::Nop => Some(()),
+            Op::Tgl(x) => {
+                let loc = (self.pc as i64).checked_add(offset(&self.value(x)));
+                if let Some(loc) = loc.filter(|loc| *loc >= 0 && *loc < self.program.len() as i64) {
+                    self.toggle(loc as usize);
+                }
+                Some(())
+            },
+            Op::Out(x) => { self.output.push(self.registers[x].clone()); Some(()) },
+            Op::Add(dst, src) => {
+                let n = self.registers[src].clone();
+                if n <= zero {
+                    return self.fallback();
+                }
+                if self.add_to(dst, &n).is_none() {
+                    return Next::Err(Trap::Overflow);
+                }
+                self.registers[src] = zero;
+                return Next::Jump(3);
+            },
+            Op::Mul(dst, factor, inner, outer) => {
+                let f = self.value(factor);
+                let n = self.registers[outer].clone();
+                if f <= zero || n <= zero {
+                    return self.fallback();
+                }
+                if f.mul(&n, self.arith).and_then(|p| self.add_to(dst, &p)).is_none() {
+                    return Next::Err(Trap::Overflow);
+                }
+                self.registers[inner] = zero.clone();
+                self.registers[outer] = zero;
+                return Next::Jump(6);
+            },
+            Op::Invalid => { return Next::Err(Trap::InvalidInstruction); },
+        };
+        match done {
+            Some(()) => Next::Ok,
+            None => Next::Err(Trap::Overflow),
+        }
+    }
+
+    // Same contract as VMShell::step: running off the end (or jumping past
+    // it) halts, and an instruction outside the dialect, or an overflow
+    // under `Arith::Checked`, is an error that leaves pc where it is.
+    pub fn step(&mut self) -> RunResult {
+        self.trap = None;
+        if self.pc >= self.program.len() {
+            return RunResult::Halt;
+        }
+        match self.execute(self.code[self.pc]) {
+            Next::Ok => { self.pc += 1; },
+            Next::Jump(n) if n < 0 => { self.pc = self.pc.overflowing_sub(n.unsigned_abs() as usize).0; },
+            Next::Jump(n) => { self.pc = self.pc.saturating_add(n as usize); },
+            Next::Err(trap) => {
+                self.trap = Some(trap);
+                return RunResult::Err;
+            },
+        }
+        self.steps += 1;
+        if self.pc > self.program.len() {
+            self.pc = usize::MAX;
+            RunResult::Halt
+        }
+        else {
+            RunResult::Ok
+        }
+    }
+
+    pub fn run(&mut self) -> RunResult {
+        loop {
+            let r = self.step();
+            if r != RunResult::Ok {
+                return r;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::{AssembunnyVM, parse_program};
+    use crate::assembunny::register::RegisterSet;
+    use crate::assembunny::test_programs::DAY23;
+
+    #[test]
+    fn compiled_test() {
+        let program = parse_program(DAY23, Dialect::TOGGLE).unwrap();
+        for optimised in [false, true] {
+            let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+            let mut cvm = CompiledVM::new(&program, Dialect::TOGGLE);
+            if optimised {
+                vm.optimise();
+                cvm.optimise();
+            }
+            vm.set_reg('a', 7);
+            cvm.set_reg('a', 7);
+            assert!(vm.run() == RunResult::Halt);
+            assert!(cvm.run() == RunResult::Halt);
+            assert_eq!(cvm.get_reg('a'), 5040 + 73 * 79);
+            assert_eq!(cvm.registers(), vm.state().registers);
+            assert_eq!(cvm.program, vm.shell.vm.program);
+            assert_eq!(cvm.steps, vm.steps);
+            assert_eq!(cvm.pc, vm.shell.vm.pc);
+            let recompiled: Vec<Op> = cvm.program.iter().map(|i| compile(i, Dialect::TOGGLE)).collect();
+            assert_eq!(cvm.code, recompiled);
+        }
+
+        let program = parse_program("out a\ntgl a\n", Dialect::FULL).unwrap();
+        let mut cvm = CompiledVM::new(&program, Dialect::TOGGLE);
+        assert!(cvm.run() == RunResult::Err);
+        assert_eq!((cvm.pc, cvm.steps), (0, 0));
+    }
+
+    #[test]
+    fn overflow_test() {
+        let program = parse_program(DAY23, Dialect::TOGGLE).unwrap();
+        let mut cvm = CompiledVM::new(&program, Dialect::TOGGLE);
+        cvm.optimise();
+        cvm.set_reg('a', 20);
+        assert!(cvm.run() == RunResult::Halt);
+        assert_eq!(cvm.get_reg('a'), 2432902008176640000 + 73 * 79);
+
+        // 21! doesn't fit in an i64.
+        let mut cvm = CompiledVM::new(&program, Dialect::TOGGLE);
+        cvm.optimise();
+        cvm.set_reg('a', 21);
+        assert!(cvm.run() == RunResult::Err);
+        assert_eq!(cvm.trap, Some(Trap::Overflow));
+
+        let mut big = CompiledVM::big(&program, Dialect::TOGGLE);
+        big.optimise();
+        big.set_reg('a', 25);
+        assert!(big.run() == RunResult::Halt);
+        assert_eq!(big.get_reg('a'), "15511210043330985984005767".parse::<BigInt>().unwrap());
+
+        let dialect = Dialect::BASIC.with_registers(RegisterSet::numbered(200));
+        let program = parse_program("cpy 3 r199\ninc r0\ndec r199\njnz r199 -2\n", dialect).unwrap();
+        let mut cvm = CompiledVM::new(&program, dialect);
+        assert!(cvm.run() == RunResult::Halt);
+        assert_eq!(cvm.registers(), vec![(Reg::Numbered(0), 3)]);
+    }
+}
@@ -0,0 +1,248 @@
+use std::cell::{Cell, RefCell};
+use ya_advent_lib::vm_shell::{CPU, VM, VMShell, InstructionResult, RunResult};
+use super::arith::{Arith, Trap, Word};
+use super::instruction::{Dialect, Instruction, RI};
+use super::optimise::{optimise, fuse_around, unfuse_around};
+use super::profile::Profile;
+use super::register::Reg;
+use super::state::MachineState;
+
+pub struct AssembunnyCPU {
+    pub dialect: Dialect,
+    pub optimise: bool,
+    pub arith: Arith,
+    pub trap: Cell<Option<Trap>>,
+    pub output: RefCell<Vec<i64>>,
+    pub profile: RefCell<Option<Profile>>,
+}
+
+impl AssembunnyCPU {
+    pub fn new(dialect: Dialect) -> Self {
+        Self {
+            dialect,
+            optimise: false,
+            arith: Arith::default(),
+            trap: Cell::new(None),
+            output: RefCell::new(Vec::new()),
+            profile: RefCell::new(None),
+        }
+    }
+
+    fn trap(&self, trap: Trap) -> InstructionResult {
+        self.trap.set(Some(trap));
+        InstructionResult::Err
+    }
+
+    fn execute(&self, vm: &mut VM<Reg, i64, Instruction>, i: &Instruction) -> InstructionResult {
+        if !self.dialect.allows(i) {
+            return self.trap(Trap::InvalidInstruction);
+        }
+        let resolve = |ri| {
+            match ri {
+                RI::Imm(x) => x,
+                RI::Reg(r) => vm.get_reg(r),
+            }
+        };
+
+        match i {
+            Instruction::Cpy(x, y) => {
+                vm.set_reg(*y, resolve(*x));
+            },
+            Instruction::Inc(x) => {
+                let Some(n) = vm.get_reg(*x).add(&1, self.arith) else {
+                    return self.trap(Trap::Overflow);
+                };
+                vm.set_reg(*x, n);
+            },
+            Instruction::Dec(x) => {
+                let Some(n) = vm.get_reg(*x).add(&-1, self.arith) else {
+                    return self.trap(Trap::Overflow);
+                };
+                vm.set_reg(*x, n);
+            },
+            Instruction::Jnz(x, y) if resolve(*x) != 0 => {
+                let jump = resolve(*y);
+                if jump < 0 {
+                    return InstructionResult::JumpBck(jump.unsigned_abs() as usize);
+                }
+                else {
+                    return InstructionResult::JumpFwd(jump as usize);
+                }
+            },
+            Instruction::Tgl(x) => {
+                let loc = (vm.pc as i64).checked_add(resolve(*x));
+                if let Some(loc) = loc.filter(|loc| *loc >= 0 && *loc < vm.program.len() as i64) {
+                    let loc = loc as usize;
+                    if let Some(profile) = self.profile.borrow_mut().as_mut() {
+                        profile.toggled[loc] += 1;
+                    }
+                    unfuse_around(&mut vm.program, loc);
+                    let ninst = vm.program[loc].toggled();
+                    vm.program[loc] = ninst;
+                    if self.optimise {
+                        fuse_around(&mut vm.program, loc);
+                    }
+                }
+            },
+            Instruction::Add(dst, src, orig) => {
+                let n = vm.get_reg(*src);
+                if n <= 0 {
+                    return self.execute(vm, orig);
+                }
+                let Some(sum) = vm.get_reg(*dst).add(&n, self.arith) else {
+                    return self.trap(Trap::Overflow);
+                };
+                vm.set_reg(*dst, sum);
+                vm.set_reg(*src, 0);
+                return InstructionResult::JumpFwd(3);
+            },
+            Instruction::Mul(dst, factor, inner, outer, orig) => {
+                let f = resolve(*factor);
+                let n = vm.get_reg(*outer);
+                if f <= 0 || n <= 0 {
+                    return self.execute(vm, orig);
+                }
+                let Some(sum) = f.mul(&n, self.arith).and_then(|p| vm.get_reg(*dst).add(&p, self.arith)) else {
+                    return self.trap(Trap::Overflow);
+                };
+                vm.set_reg(*dst, sum);
+                vm.set_reg(*inner, 0);
+                vm.set_reg(*outer, 0);
+                return InstructionResult::JumpFwd(6);
+            },
+            Instruction::Out(x) => {
+                self.output.borrow_mut().push(vm.get_reg(*x));
+            },
+            _ => {},
+        }
+        InstructionResult::Ok
+    }
+}
+
+impl CPU<Reg, i64, Instruction> for AssembunnyCPU {
+    fn execute_instruction(&self, vm: &mut VM<Reg, i64, Instruction>, i: &Instruction) -> InstructionResult {
+        let pc = vm.pc;
+        // (inner, outer) pass counts, should this run a fused loop
+        let passes = match i {
+            Instruction::Add(_, src, _) => (vm.get_reg(*src), 1),
+            Instruction::Mul(_, RI::Imm(f), _, outer, _) => (*f, vm.get_reg(*outer)),
+            Instruction::Mul(_, RI::Reg(f), _, outer, _) => (vm.get_reg(*f), vm.get_reg(*outer)),
+            _ => (0, 0),
+        };
+        let r = self.execute(vm, i);
+        if let Some(profile) = self.profile.borrow_mut().as_mut() {
+            match r {
+                InstructionResult::JumpFwd(n) if n == i.fused_len() && n > 1 =>
+                    profile.record_loop(pc, i, passes.0 as usize, passes.1 as usize),
+                _ => profile.record(pc, i, r),
+            }
+        }
+        r
+    }
+}
+
+pub struct AssembunnyVM {
+    pub cpu: AssembunnyCPU,
+    pub shell: VMShell<Reg, i64, Instruction>,
+    pub steps: usize,
+}
+
+impl AssembunnyVM {
+    pub fn new(program: &[Instruction], dialect: Dialect) -> Self {
+        let cpu = AssembunnyCPU::new(dialect);
+        let shell = VMShell::new(program.to_owned(), 0);
+        Self { cpu, shell, steps: 0 }
+    }
+    pub fn get_reg(&self, r: impl Into<Reg>) -> i64 {
+        self.shell.vm.get_reg(r.into())
+    }
+    pub fn set_reg(&mut self, r: impl Into<Reg>, v: i64) {
+        self.shell.vm.set_reg(r.into(), v);
+    }
+    pub fn set_arith(&mut self, arith: Arith) {
+        self.cpu.arith = arith;
+    }
+    // Why the last `step` returned `RunResult::Err`.
+    pub fn trap(&self) -> Option<Trap> {
+        self.cpu.trap.get()
+    }
+    // Replaces recognisable add/multiply loops with fused instructions.
+    pub fn optimise(&mut self) {
+        self.shell.vm.program = optimise(&self.shell.vm.program);
+        self.cpu.optimise = true;
+    }
+    // Starts counting executions, jumps and toggles per program slot; see
+    // `Profile::listing`.
+    pub fn enable_profiling(&mut self) {
+        *self.cpu.profile.borrow_mut() = Some(Profile::new(self.shell.vm.program.len()));
+    }
+    pub fn profile(&self) -> Option<Profile> {
+        self.cpu.profile.borrow().clone()
+    }
+    pub fn step(&mut self) -> RunResult {
+        let running = self.shell.vm.pc < self.shell.vm.program.len();
+        self.cpu.trap.set(None);
+        let r = self.shell.step(&self.cpu);
+        if running && r != RunResult::Err {
+            self.steps += 1;
+        }
+        r
+    }
+    pub fn run(&mut self) -> RunResult {
+        loop {
+            let r = self.step();
+            if r != RunResult::Ok {
+                return r;
+            }
+        }
+    }
+    // Drains whatever `out` has produced since the last call.
+    pub fn take_output(&self) -> Vec<i64> {
+        self.cpu.output.take()
+    }
+    pub fn state(&self) -> MachineState {
+        MachineState::new(&self.shell.vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ya_advent_lib::read::test_input;
+
+    #[test]
+    fn dialect_test() {
+        let input: Vec<Instruction> = test_input(
+            "cpy 1 a\n\
+             tgl a\n\
+             inc a\n"
+        );
+        let mut vm = AssembunnyVM::new(&input, Dialect::BASIC);
+        assert!(vm.run() == RunResult::Err);
+        assert_eq!(vm.shell.vm.pc, 1);
+        assert_eq!(vm.trap(), Some(Trap::InvalidInstruction));
+        let mut vm = AssembunnyVM::new(&input, Dialect::TOGGLE);
+        assert!(vm.run() == RunResult::Halt);
+        assert_eq!(vm.get_reg('a'), 0);
+    }
+
+    #[test]
+    fn overflow_test() {
+        let input: Vec<Instruction> = test_input(
+            "inc a\n\
+             dec b\n"
+        );
+        let mut vm = AssembunnyVM::new(&input, Dialect::BASIC);
+        vm.set_reg('a', i64::MAX);
+        assert!(vm.run() == RunResult::Err);
+        assert_eq!(vm.trap(), Some(Trap::Overflow));
+        assert_eq!((vm.shell.vm.pc, vm.get_reg('a')), (0, i64::MAX));
+
+        let mut vm = AssembunnyVM::new(&input, Dialect::BASIC);
+        vm.set_arith(Arith::Wrapping);
+        vm.set_reg('a', i64::MAX);
+        vm.set_reg('b', i64::MIN);
+        assert!(vm.run() == RunResult::Halt);
+        assert_eq!((vm.get_reg('a'), vm.get_reg('b')), (i64::MIN, i64::MAX));
+    }
+}
@@ -0,0 +1,348 @@
+use std::collections::BTreeSet;
+use super::instruction::{Instruction, RI};
+use super::optimise::{match_add, match_mul};
+use super::register::Reg;
+
+enum Stmt {
+    Line(usize, String),
+    Loop(usize, Option<String>, Vec<Stmt>),
+    If(usize, String, Vec<Stmt>),
+    // Code after a jump whose target is held in a register; we can't know
+    // where it goes, so the rest of the block is left as assembly.
+    SelfModifying(Reg, Vec<String>),
+}
+
+fn operand(ri: &RI) -> String {
+    match ri {
+        RI::Reg(r) => r.to_string(),
+        RI::Imm(i) => i.to_string(),
+    }
+}
+
+fn is_true(ri: &RI) -> bool {
+    matches!(ri, RI::Imm(i) if *i != 0)
+}
+
+struct Decompiler {
+    program: Vec<Instruction>,
+    labels: BTreeSet<usize>,
+}
+
+impl Decompiler {
+    // Where the jump at `pc` goes, if it is a jump that can be taken and
+    // its target is fixed. A target too far to add up is left out; it
+    // couldn't land in the program anyway.
+    fn static_target(&self, pc: usize) -> Option<i64> {
+        match &self.program[pc] {
+            Instruction::Jnz(RI::Imm(0), _) => None,
+            Instruction::Jnz(_, RI::Imm(off)) => (pc as i64).checked_add(*off),
+            _ => None,
+        }
+    }
+
+    // True if some jump outside [start, start+len) lands strictly inside it,
+    // in which case the slots can't be folded into a single statement.
+    fn entered_from_outside(&self, start: usize, len: usize) -> bool {
+        let inside = |t: i64| t > start as i64 && t < (start + len) as i64;
+        (0..self.program.len())
+            .filter(|pc| *pc < start || *pc >= start + len)
+            .any(|pc| self.static_target(pc).is_some_and(inside))
+    }
+
+    //   cpy K c
+    //   jnz n 2
+    //   jnz 1 6
+    //   dec n
+    //   dec c
+    //   jnz c -4
+    //   inc q
+    //   jnz 1 -7
+    fn match_divmod(&self, pc: usize) -> Option<(Reg, Reg, RI, Reg)> {
+        let s = self.program.get(pc..pc + 8)?;
+        let (k, c) = match &s[0] { Instruction::Cpy(k, c) => (*k, *c), _ => { return None; } };
+        let n = match &s[1] { Instruction::Jnz(RI::Reg(n), RI::Imm(2)) => *n, _ => { return None; } };
+        match (&s[2], &s[3], &s[4], &s[5], &s[7]) {
+            (Instruction::Jnz(x, RI::Imm(6)),
+             Instruction::Dec(n2),
+             Instruction::Dec(c2),
+             Instruction::Jnz(RI::Reg(c3), RI::Imm(-4)),
+             Instruction::Jnz(y, RI::Imm(-7)))
+                if is_true(x) && is_true(y) && *n2 == n && *c2 == c && *c3 == c => {},
+            _ => { return None; },
+        }
+        let q = match &s[6] { Instruction::Inc(q) => *q, _ => { return None; } };
+        if n == c || q == n || q == c || k == RI::Reg(n) || k == RI::Reg(c) || k == RI::Reg(q) {
+            return None;
+        }
+        Some((q, n, k, c))
+    }
+
+    fn idiom(&self, pc: usize, end: usize) -> Option<(String, usize)> {
+        let fits = |len: usize| pc + len <= end && !self.entered_from_outside(pc, len);
+        if let Some((q, n, k, c)) = self.match_divmod(pc).filter(|_| fits(8)) {
+            let k = operand(&k);
+            return Some((format!("{q} += {n} / {k}; {c} = {k} - {n} % {k}; {n} = 0"), 8));
+        }
+        if let Some((dst, factor, inner, outer)) = match_mul(&self.program, pc).filter(|_| fits(6)) {
+            let f = operand(&factor);
+            return Some((format!("{dst} += {f} * {outer}; {inner} = 0; {outer} = 0"), 6));
+        }
+        if let Some((dst, src)) = match_add(&self.program, pc).filter(|_| fits(3)) {
+            return Some((format!("{dst} += {src}; {src} = 0"), 3));
+        }
+        None
+    }
+
+    fn goto(&mut self, target: i64) -> String {
+        if target < 0 || target >= self.program.len() as i64 {
+            "halt".to_string()
+        }
+        else {
+            self.labels.insert(target as usize);
+            format!("goto L{target}")
+        }
+    }
+
+    // Structures the slots [start, end). `exit` is the slot just after the
+    // innermost enclosing loop, which jumps can reach with `break`.
+    fn block(&mut self, start: usize, end: usize, exit: Option<usize>) -> Vec<Stmt> {
+        let mut out = Vec::new();
+        let mut pc = start;
+        while pc < end {
+            if let Some((text, len)) = self.idiom(pc, end) {
+                out.push(Stmt::Line(pc, text));
+                pc += len;
+                continue;
+            }
+            if let Some(j) = (pc..end).rev().find(|j| self.static_target(*j) == Some(pc as i64)) {
+                let cond = match &self.program[j] {
+                    Instruction::Jnz(x, _) if !is_true(x) => Some(format!("{} != 0", operand(x))),
+                    _ => None,
+                };
+                let body = self.block(pc, j, Some(j + 1));
+                out.push(Stmt::Loop(pc, cond, body));
+                pc = j + 1;
+                continue;
+            }
+            let stmt = match self.program[pc].clone() {
+                Instruction::Cpy(x, y) => format!("{y} = {}", operand(&x)),
+                Instruction::Inc(x) => format!("{x} += 1"),
+                Instruction::Dec(x) => format!("{x} -= 1"),
+                Instruction::Out(x) => format!("out({x})"),
+                Instruction::Tgl(RI::Imm(x)) => match (pc as i64).checked_add(x) {
+                    Some(t) => format!("toggle({t})  // self-modifying"),
+                    None => format!("toggle({pc} + {x})  // self-modifying"),
+                },
+                Instruction::Tgl(RI::Reg(x)) => format!("toggle({pc} + {x})  // self-modifying"),
+                Instruction::Jnz(_, RI::Reg(r)) => {
+                    let lines = (pc..end)
+                        .map(|i| format!("{i:>4}: {}", self.program[i].render()))
+                        .collect();
+                    out.push(Stmt::SelfModifying(r, lines));
+                    break;
+                },
+                Instruction::Jnz(RI::Imm(0), _) |
+                Instruction::Jnz(_, RI::Imm(1)) => "nop".to_string(),
+                Instruction::Jnz(x, RI::Imm(off)) => {
+                    // only a forward jump can overflow, and it runs off the end
+                    let target = (pc as i64).checked_add(off).unwrap_or(i64::MAX);
+                    let cond = operand(&x);
+                    if exit.is_some_and(|e| e as i64 == target) {
+                        if is_true(&x) { "break".to_string() } else { format!("if ({cond} != 0) break") }
+                    }
+                    else if off > 1 && target <= end as i64 && !is_true(&x) {
+                        let t = target as usize;
+                        // `jnz x 2 / jnz 1 k` is "if x is set, run the next k-1 slots"
+                        let skip = match &self.program[pc + 1] {
+                            Instruction::Jnz(y, RI::Imm(k)) if off == 2 && is_true(y) && *k > 1
+                                && pc + 1 + (*k as usize) <= end => Some(pc + 1 + *k as usize),
+                            _ => None,
+                        };
+                        if let Some(skip_to) = skip {
+                            let body = self.block(pc + 2, skip_to, exit);
+                            out.push(Stmt::If(pc, format!("{cond} != 0"), body));
+                            pc = skip_to;
+                        }
+                        else {
+                            let body = self.block(pc + 1, t, exit);
+                            out.push(Stmt::If(pc, format!("{cond} == 0"), body));
+                            pc = t;
+                        }
+                        continue;
+                    }
+                    else if is_true(&x) {
+                        self.goto(target)
+                    }
+                    else {
+                        format!("if ({cond} != 0) {}", self.goto(target))
+                    }
+                },
+                inst => format!("nop  // invalid: {}", inst.render()),
+            };
+            out.push(Stmt::Line(pc, stmt));
+            pc += 1;
+        }
+        out
+    }
+
+    fn is_bare_break(&self, body: &[Stmt]) -> bool {
+        matches!(body, [Stmt::Line(pc, t)] if t == "break" && !self.labels.contains(pc))
+    }
+
+    fn print(&self, stmts: &[Stmt], indent: usize, out: &mut String) {
+        let pad = "    ".repeat(indent);
+        for stmt in stmts {
+            let pc = match stmt {
+                Stmt::Line(pc, _) | Stmt::Loop(pc, _, _) | Stmt::If(pc, _, _) => Some(*pc),
+                Stmt::SelfModifying(..) => None,
+            };
+            if let Some(pc) = pc.filter(|pc| self.labels.contains(pc)) {
+                out.push_str(&format!("{pad}L{pc}:\n"));
+            }
+            match stmt {
+                Stmt::Line(_, text) => {
+                    out.push_str(&format!("{pad}{text}\n"));
+                },
+                Stmt::Loop(_, cond, body) => {
+                    out.push_str(&format!("{pad}{} {{\n", if cond.is_some() { "do" } else { "loop" }));
+                    self.print(body, indent + 1, out);
+                    match cond {
+                        Some(c) => out.push_str(&format!("{pad}}} while ({c})\n")),
+                        None => out.push_str(&format!("{pad}}}\n")),
+                    }
+                },
+                Stmt::If(_, cond, body) if self.is_bare_break(body) => {
+                    out.push_str(&format!("{pad}if ({cond}) break\n"));
+                },
+                Stmt::If(_, cond, body) => {
+                    out.push_str(&format!("{pad}if ({cond}) {{\n"));
+                    self.print(body, indent + 1, out);
+                    out.push_str(&format!("{pad}}}\n"));
+                },
+                Stmt::SelfModifying(reg, lines) => {
+                    out.push_str(&format!("{pad}// self-modifying: jump target depends on {reg}\n"));
+                    out.push_str(&format!("{pad}asm {{\n"));
+                    for l in lines {
+                        out.push_str(&format!("{pad}    {l}\n"));
+                    }
+                    out.push_str(&format!("{pad}}}\n"));
+                },
+            }
+        }
+    }
+}
+
+// Turns a program back into structured pseudo-code: backward jumps become
+// loops, forward jumps become ifs (or breaks/gotos when they don't nest),
+// and the add, multiply and divmod loop idioms are folded into expressions.
+pub fn decompile(program: &[Instruction]) -> String {
+    let mut d = Decompiler {
+        program: program.iter().map(|i| i.original().clone()).collect(),
+        labels: BTreeSet::new(),
+    };
+    let stmts = d.block(0, program.len(), None);
+    let mut out = String::new();
+    if d.program.iter().any(|i| matches!(i, Instruction::Tgl(_))) {
+        out.push_str("// uses tgl: this is the program as written, toggles may change it\n");
+    }
+    d.print(&stmts, 0, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::{Dialect, parse_program};
+    use crate::assembunny::test_programs::{DAY12, DAY23, DAY25};
+
+    #[test]
+    fn decompile_test() {
+        let program = parse_program(DAY12, Dialect::BASIC).unwrap();
+        assert_eq!(decompile(&program),
+            "a = 1\n\
+             b = 1\n\
+             d = 26\n\
+             if (c != 0) {\n\
+             \x20   c = 7\n\
+             \x20   d += c; c = 0\n\
+             }\n\
+             do {\n\
+             \x20   c = a\n\
+             \x20   a += b; b = 0\n\
+             \x20   b = c\n\
+             \x20   d -= 1\n\
+             } while (d != 0)\n\
+             c = 13\n\
+             a += 14 * c; d = 0; c = 0\n"
+        );
+        let program = parse_program(DAY23, Dialect::TOGGLE).unwrap();
+        assert_eq!(decompile(&program),
+            "// uses tgl: this is the program as written, toggles may change it\n\
+             b = a\n\
+             b -= 1\n\
+             d = a\n\
+             a = 0\n\
+             a += b * d; c = 0; d = 0\n\
+             b -= 1\n\
+             c = b\n\
+             d = c\n\
+             c += d; d = 0\n\
+             toggle(16 + c)  // self-modifying\n\
+             c = -16\n\
+             // self-modifying: jump target depends on c\n\
+             asm {\n\
+             \x20     18: jnz 1 c\n\
+             \x20     19: cpy 73 c\n\
+             \x20     20: jnz 79 d\n\
+             \x20     21: inc a\n\
+             \x20     22: inc d\n\
+             \x20     23: jnz d -2\n\
+             \x20     24: inc c\n\
+             \x20     25: jnz c -5\n\
+             }\n"
+        );
+        let program = parse_program(DAY25, Dialect::FULL).unwrap();
+        assert_eq!(decompile(&program),
+            "d = a\n\
+             c = 4\n\
+             d += 633 * c; b = 0; c = 0\n\
+             loop {\n\
+             \x20   a = d\n\
+             \x20   do {\n\
+             \x20       nop\n\
+             \x20       b = a\n\
+             \x20       a = 0\n\
+             \x20       a += b / 2; c = 2 - b % 2; b = 0\n\
+             \x20       b = 2\n\
+             \x20       loop {\n\
+             \x20           if (c == 0) break\n\
+             \x20           b -= 1\n\
+             \x20           c -= 1\n\
+             \x20       }\n\
+             \x20       nop\n\
+             \x20       out(b)\n\
+             \x20   } while (a != 0)\n\
+             }\n"
+        );
+
+        let program = parse_program("jnz a 3\ntgl 2\njnz 1 c\n", Dialect::FULL).unwrap();
+        assert_eq!(decompile(&program),
+            "// uses tgl: this is the program as written, toggles may change it\n\
+             if (a == 0) {\n\
+             \x20   toggle(3)  // self-modifying\n\
+             \x20   // self-modifying: jump target depends on c\n\
+             \x20   asm {\n\
+             \x20          2: jnz 1 c\n\
+             \x20   }\n\
+             }\n"
+        );
+
+        let program = parse_program("cpy 1 a\njnz a 9223372036854775807\ntgl 9223372036854775807\n", Dialect::FULL).unwrap();
+        assert_eq!(decompile(&program),
+            "// uses tgl: this is the program as written, toggles may change it\n\
+             a = 1\n\
+             if (a != 0) halt\n\
+             toggle(2 + 9223372036854775807)  // self-modifying\n"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseErrorKind {
+    MissingOpcode,
+    UnknownOpcode,
+    OperandCount { expected: usize, found: usize },
+    ExpectedRegister,
+    ExpectedImmediate,
+    ExpectedOperand,
+    ImmediateOutOfRange,
+    NotInDialect,
+    UnknownRegister,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseError {
+    pub line: Option<usize>,
+    pub token: String,
+    pub kind: ParseErrorKind,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, token: &str) -> Self {
+        Self { line: None, token: token.to_string(), kind }
+    }
+    pub fn at_line(self, line: usize) -> Self {
+        Self { line: Some(line), ..self }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line {line}: ")?;
+        }
+        let tok = &self.token;
+        match &self.kind {
+            ParseErrorKind::MissingOpcode => write!(f, "missing opcode"),
+            ParseErrorKind::UnknownOpcode => write!(f, "unknown opcode `{tok}`"),
+            ParseErrorKind::OperandCount { expected: 1, found } =>
+                write!(f, "{tok} needs 1 operand, found {found}"),
+            ParseErrorKind::OperandCount { expected, found } =>
+                write!(f, "{tok} needs {expected} operands, found {found}"),
+            ParseErrorKind::ExpectedRegister =>
+                write!(f, "expected a register, found `{tok}`"),
+            ParseErrorKind::ExpectedImmediate =>
+                write!(f, "expected an integer in a skipped instruction, found `{tok}`"),
+            ParseErrorKind::ExpectedOperand =>
+                write!(f, "expected a register or integer, found `{tok}`"),
+            ParseErrorKind::ImmediateOutOfRange => write!(f, "immediate `{tok}` out of range"),
+            ParseErrorKind::NotInDialect => write!(f, "`{tok}` is not available in this dialect"),
+            ParseErrorKind::UnknownRegister => write!(f, "register `{tok}` is not in this register set"),
+        }
+    }
+}
+
+impl Error for ParseError {}
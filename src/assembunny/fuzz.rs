@@ -0,0 +1,351 @@
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use ya_advent_lib::vm_shell::RunResult;
+use super::compiled::CompiledVM;
+use super::cpu::AssembunnyVM;
+use super::instruction::{Dialect, Instruction, RI};
+use super::limit::RunStatus;
+use super::printer::write_program;
+use super::register::Reg;
+
+// xorshift64*: plenty for generating programs, and a run can be repeated
+// from its seed.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+    // Inclusive at both ends.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + self.below((hi - lo + 1) as usize) as i64
+    }
+}
+
+const REGS: [char; 4] = ['a', 'b', 'c', 'd'];
+
+fn random_reg(rng: &mut Rng) -> Reg {
+    Reg::Named(REGS[rng.below(REGS.len())])
+}
+
+fn random_ri(rng: &mut Rng, lo: i64, hi: i64) -> RI {
+    if rng.below(3) == 0 {
+        RI::Reg(random_reg(rng))
+    }
+    else {
+        RI::Imm(rng.range(lo, hi))
+    }
+}
+
+// A program of roughly `len` instructions over registers a-d, using every
+// FULL opcode. Jump and toggle offsets mostly stay inside the program, and
+// the add and multiply loop idioms are planted now and then so the
+// optimiser has something to fuse.
+pub fn random_program(rng: &mut Rng, len: usize) -> Vec<Instruction> {
+    let span = len as i64;
+    let mut program = Vec::new();
+    while program.len() < len {
+        let (x, y, z) = (random_reg(rng), random_reg(rng), random_reg(rng));
+        match rng.below(20) {
+            0 => {
+                program.extend([
+                    Instruction::Inc(x),
+                    Instruction::Dec(y),
+                    Instruction::Jnz(RI::Reg(y), RI::Imm(-2)),
+                ]);
+            },
+            1 => {
+                program.extend([
+                    Instruction::Cpy(random_ri(rng, 0, 3), y),
+                    Instruction::Inc(x),
+                    Instruction::Dec(y),
+                    Instruction::Jnz(RI::Reg(y), RI::Imm(-2)),
+                    Instruction::Dec(z),
+                    Instruction::Jnz(RI::Reg(z), RI::Imm(-5)),
+                ]);
+            },
+            2..=4 => { program.push(Instruction::Cpy(random_ri(rng, -3, 5), x)); },
+            5..=7 => { program.push(Instruction::Inc(x)); },
+            8..=10 => { program.push(Instruction::Dec(x)); },
+            11..=15 => { program.push(Instruction::Jnz(random_ri(rng, 0, 1), random_ri(rng, -span, span))); },
+            16..=18 => { program.push(Instruction::Tgl(random_ri(rng, -span, span))); },
+            _ => { program.push(Instruction::Out(x)); },
+        }
+    }
+    program
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    Interpreter,
+    OptimisedInterpreter,
+    Compiled,
+    OptimisedCompiled,
+}
+
+pub const BACKENDS: [Backend; 4] = [
+    Backend::Interpreter,
+    Backend::OptimisedInterpreter,
+    Backend::Compiled,
+    Backend::OptimisedCompiled,
+];
+
+impl Backend {
+    fn optimised(&self) -> bool {
+        matches!(self, Backend::OptimisedInterpreter | Backend::OptimisedCompiled)
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Interpreter => write!(f, "interpreter"),
+            Backend::OptimisedInterpreter => write!(f, "optimised interpreter"),
+            Backend::Compiled => write!(f, "compiled"),
+            Backend::OptimisedCompiled => write!(f, "optimised compiled"),
+        }
+    }
+}
+
+// Everything a run can be judged by. The program is the one left behind by
+// `tgl`, with fused instructions written as the loop heads they replaced.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Outcome {
+    pub status: RunStatus,
+    pub registers: Vec<(Reg, i64)>,
+    pub output: Vec<i64>,
+    pub program: Vec<Instruction>,
+}
+
+fn unfused(program: &[Instruction]) -> Vec<Instruction> {
+    program.iter().map(|i| i.original().clone()).collect()
+}
+
+pub fn run_on(backend: Backend, program: &[Instruction], max_steps: usize) -> Outcome {
+    if matches!(backend, Backend::Interpreter | Backend::OptimisedInterpreter) {
+        let mut vm = AssembunnyVM::new(program, Dialect::FULL);
+        if backend.optimised() {
+            vm.optimise();
+        }
+        let status = loop {
+            if vm.steps >= max_steps {
+                break RunStatus::StepLimitReached;
+            }
+            match vm.step() {
+                RunResult::Ok | RunResult::Break => {},
+                RunResult::Halt => { break RunStatus::Halted; },
+                RunResult::Err => { break RunStatus::Trapped(vm.trap().unwrap()); },
+            }
+        };
+        Outcome {
+            status,
+            registers: vm.state().registers,
+            output: vm.take_output(),
+            program: unfused(&vm.shell.vm.program),
+        }
+    }
+    else {
+        let mut vm = CompiledVM::new(program, Dialect::FULL);
+        if backend.optimised() {
+            vm.optimise();
+        }
+        let status = loop {
+            if vm.steps >= max_steps {
+                break RunStatus::StepLimitReached;
+            }
+            match vm.step() {
+                RunResult::Ok | RunResult::Break => {},
+                RunResult::Halt => { break RunStatus::Halted; },
+                RunResult::Err => { break RunStatus::Trapped(vm.trap.unwrap()); },
+            }
+        };
+        Outcome {
+            status,
+            registers: vm.registers(),
+            output: vm.take_output(),
+            program: unfused(&vm.program),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Disagreement {
+    pub program: Vec<Instruction>,
+    pub backend: Backend,
+    pub expected: Outcome,
+    pub found: Outcome,
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} disagrees with the interpreter on:", self.backend)?;
+        write!(f, "{}", write_program(&self.program))?;
+        writeln!(f, "expected {:?}", self.expected)?;
+        write!(f, "found    {:?}", self.found)
+    }
+}
+
+// Runs the program on every back end and compares each with the plain
+// interpreter. Fused instructions cover many steps in one, so the optimised
+// back ends are only compared when the interpreter finished inside
+// `max_steps`; the unoptimised ones have to match step for step either way.
+pub fn check(program: &[Instruction], max_steps: usize) -> Result<(), Box<Disagreement>> {
+    let expected = run_on(Backend::Interpreter, program, max_steps);
+    for backend in &BACKENDS[1..] {
+        if backend.optimised() && expected.status == RunStatus::StepLimitReached {
+            continue;
+        }
+        let found = run_on(*backend, program, max_steps);
+        if found != expected {
+            return Err(Box::new(Disagreement {
+                program: program.to_vec(),
+                backend: *backend,
+                expected,
+                found,
+            }));
+        }
+    }
+    Ok(())
+}
+
+// Every program one step simpler: one instruction dropped, or one immediate
+// halved towards zero.
+fn simplifications(program: &[Instruction]) -> Vec<Vec<Instruction>> {
+    let mut out = Vec::new();
+    for idx in 0..program.len() {
+        let mut p = program.to_vec();
+        p.remove(idx);
+        out.push(p);
+    }
+    let halve = |ri: &RI| match ri {
+        RI::Imm(i) if *i != 0 => Some(RI::Imm(i / 2)),
+        _ => None,
+    };
+    for (idx, inst) in program.iter().enumerate() {
+        let simpler = match inst {
+            Instruction::Cpy(x, y) => halve(x).map(|x| vec![Instruction::Cpy(x, *y)]),
+            Instruction::Tgl(x) => halve(x).map(|x| vec![Instruction::Tgl(x)]),
+            Instruction::Jnz(x, y) => Some(
+                [halve(x).map(|x| Instruction::Jnz(x, *y)), halve(y).map(|y| Instruction::Jnz(*x, y))]
+                    .into_iter().flatten().collect()),
+            _ => None,
+        };
+        for inst in simpler.into_iter().flatten() {
+            let mut p = program.to_vec();
+            p[idx] = inst;
+            out.push(p);
+        }
+    }
+    out
+}
+
+// Greedily simplifies the program for as long as `still_fails` holds.
+pub fn shrink(program: &[Instruction], still_fails: impl Fn(&[Instruction]) -> bool) -> Vec<Instruction> {
+    let mut best = program.to_vec();
+    while let Some(p) = simplifications(&best).into_iter().find(|p| still_fails(p)) {
+        best = p;
+    }
+    best
+}
+
+// Checks `count` random programs and returns each disagreement found,
+// shrunk to a minimal program that still shows it.
+pub fn fuzz(seed: u64, count: usize, max_steps: usize) -> Vec<Disagreement> {
+    let mut rng = Rng::new(seed);
+    (0..count)
+        .filter_map(|_| {
+            let len = 4 + rng.below(16);
+            let program = random_program(&mut rng, len);
+            check(&program, max_steps).err()?;
+            let program = shrink(&program, |p| check(p, max_steps).is_err());
+            check(&program, max_steps).err().map(|d| *d)
+        })
+        .collect()
+}
+
+// Writes the program into `dir` as a test case named after its contents, so
+// saving the same case twice is harmless.
+pub fn save_case(dir: &Path, program: &[Instruction]) -> io::Result<PathBuf> {
+    let src = write_program(program);
+    let mut hasher = DefaultHasher::new();
+    src.hash(&mut hasher);
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{:016x}.testinput", hasher.finish()));
+    fs::write(&path, src)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::parse_program;
+
+    const MAX_STEPS: usize = 2000;
+
+    fn cases_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("src/assembunny/fuzz_cases")
+    }
+
+    // Failures are only written to `fuzz_cases` when FUZZ_SAVE_CASES is set,
+    // so a plain test run never touches the source tree.
+    #[test]
+    fn fuzz_test() {
+        let failures = fuzz(2016, 500, MAX_STEPS);
+        if std::env::var_os("FUZZ_SAVE_CASES").is_some() {
+            for d in &failures {
+                let path = save_case(&cases_dir(), &d.program).unwrap();
+                eprintln!("saved {}", path.display());
+            }
+        }
+        if let Some(d) = failures.first() {
+            panic!("{} disagreement(s), first:\n{d}", failures.len());
+        }
+    }
+
+    // Replays the cases checked in under `fuzz_cases`.
+    #[test]
+    fn saved_cases_test() {
+        let Ok(entries) = fs::read_dir(cases_dir()) else { return; };
+        for entry in entries {
+            let path = entry.unwrap().path();
+            let program = parse_program(&fs::read_to_string(&path).unwrap(), Dialect::FULL).unwrap();
+            if let Err(d) = check(&program, MAX_STEPS) {
+                panic!("{}: {d}", path.display());
+            }
+        }
+    }
+
+    #[test]
+    fn shrink_test() {
+        let program = parse_program(
+            "cpy 40 a\n\
+             inc b\n\
+             out b\n\
+             tgl 2\n\
+             jnz 1 3\n\
+             dec a\n",
+            Dialect::FULL,
+        ).unwrap();
+        // "a ends up above 5" needs only the cpy, with the smallest
+        // immediate that still works.
+        let fails = |p: &[Instruction]| {
+            run_on(Backend::Interpreter, p, MAX_STEPS).registers.iter()
+                .any(|(r, v)| *r == Reg::Named('a') && *v > 5)
+        };
+        let shrunk = shrink(&program, fails);
+        assert_eq!(write_program(&shrunk), "cpy 10 a\n");
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use ya_advent_lib::vm_debugger::Debugger;
+use ya_advent_lib::vm_shell::RunResult;
+use num_bigint::BigInt;
+use super::arith::{Arith, Trap};
+use super::compiled::CompiledVM;
+use super::instruction::{Dialect, Instruction};
+use super::cpu::AssembunnyVM;
+use super::register::Reg;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+// A register condition such as `a == 0` or `d > 100`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Watch {
+    pub reg: Reg,
+    pub cmp: Cmp,
+    pub value: i64,
+}
+
+impl Watch {
+    pub fn holds(&self, vm: &AssembunnyVM) -> bool {
+        let r = vm.get_reg(self.reg);
+        match self.cmp {
+            Cmp::Eq => r == self.value,
+            Cmp::Ne => r != self.value,
+            Cmp::Lt => r < self.value,
+            Cmp::Le => r <= self.value,
+            Cmp::Gt => r > self.value,
+            Cmp::Ge => r >= self.value,
+        }
+    }
+}
+
+impl FromStr for Watch {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let [reg, cmp, value] = words[..] else {
+            return Err(format!("expected `<register> <op> <value>`, found `{s}`"));
+        };
+        let reg = reg.parse::<Reg>().map_err(|_| format!("`{reg}` is not a register"))?;
+        let cmp = match cmp {
+            "==" => Cmp::Eq,
+            "!=" => Cmp::Ne,
+            "<" => Cmp::Lt,
+            "<=" => Cmp::Le,
+            ">" => Cmp::Gt,
+            ">=" => Cmp::Ge,
+            _ => { return Err(format!("unknown comparison `{cmp}`")); },
+        };
+        let value = value.parse::<i64>().map_err(|_| format!("`{value}` is not an integer"))?;
+        Ok(Watch { reg, cmp, value })
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cmp = match self.cmp {
+            Cmp::Eq => "==",
+            Cmp::Ne => "!=",
+            Cmp::Lt => "<",
+            Cmp::Le => "<=",
+            Cmp::Gt => ">",
+            Cmp::Ge => ">=",
+        };
+        write!(f, "{} {cmp} {}", self.reg, self.value)
+    }
+}
+
+// What to do when running a program: either hand it to the interactive
+// debugger, or run it headless until it halts or one of the stop
+// conditions triggers.
+#[derive(Clone, Debug, Default)]
+pub struct RunConfig {
+    pub breakpoints: HashSet<usize>,
+    pub watches: Vec<Watch>,
+    pub max_steps: Option<usize>,
+    pub registers: Vec<(Reg, i64)>,
+    pub arith: Arith,
+    pub debug: bool,
+    pub profile: bool,
+    pub decompile: bool,
+    pub symbolic: Option<Vec<Reg>>,
+}
+
+fn parse_set(s: &str) -> Result<(Reg, i64), String> {
+    let (reg, value) = s.split_once('=')
+        .ok_or_else(|| format!("expected `<register>=<value>`, found `{s}`"))?;
+    let watch = format!("{} == {}", reg.trim(), value.trim()).parse::<Watch>()?;
+    Ok((watch.reg, watch.value))
+}
+
+impl RunConfig {
+    // Applies one script command:
+    //   break <pc>
+    //   watch <register> <op> <value>
+    //   limit <steps>
+    //   set <register>=<value>
+    //   arith wrapping|checked|big
+    //   debug
+    //   profile
+    pub fn apply_command(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        let (cmd, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let arg = arg.trim();
+        match cmd {
+            "break" => {
+                let pc = arg.parse::<usize>().map_err(|_| format!("`{arg}` is not a program counter"))?;
+                self.breakpoints.insert(pc);
+            },
+            "watch" => {
+                self.watches.push(arg.parse()?);
+            },
+            "limit" => {
+                let n = arg.parse::<usize>().map_err(|_| format!("`{arg}` is not a step count"))?;
+                self.max_steps = Some(n);
+            },
+            "set" => {
+                self.registers.push(parse_set(arg)?);
+            },
+            "arith" => {
+                self.arith = arg.parse()?;
+            },
+            "debug" if arg.is_empty() => {
+                self.debug = true;
+            },
+            "profile" if arg.is_empty() => {
+                self.profile = true;
+            },
+            _ => { return Err(format!("unknown command `{line}`")); },
+        }
+        Ok(())
+    }
+
+    // For `Arith::Big`: runs the program on `CompiledVM<BigInt>` with
+    // `inputs` and then the `set` overrides in the registers, stopping at
+    // the step limit if there is one. That VM has no debugger, profiler,
+    // breakpoints or watches, so asking for any of them is an error.
+    pub fn run_big(
+        &self,
+        program: &[Instruction],
+        dialect: Dialect,
+        inputs: &[(Reg, i64)],
+    ) -> Result<CompiledVM<BigInt>, String> {
+        if self.debug || self.profile || !self.breakpoints.is_empty() || !self.watches.is_empty() {
+            return Err("--arith big has no debugger, profiler, breakpoints or watches".to_string());
+        }
+        let mut vm = CompiledVM::big(program, dialect);
+        vm.optimise();
+        for (r, v) in inputs.iter().chain(&self.registers) {
+            vm.set_reg(*r, *v);
+        }
+        loop {
+            if self.max_steps.is_some_and(|max| vm.steps >= max) {
+                return Err(format!("stopped after {} steps at pc {}: step limit reached", vm.steps, vm.pc));
+            }
+            match vm.step() {
+                RunResult::Ok | RunResult::Break => {},
+                RunResult::Halt => { return Ok(vm); },
+                RunResult::Err => {
+                    return Err(format!("stopped after {} steps at pc {}: {}", vm.steps, vm.pc, vm.trap.unwrap()));
+                },
+            }
+        }
+    }
+
+    // True if a headless run would stop somewhere other than a halt.
+    pub fn has_stop_condition(&self) -> bool {
+        !self.breakpoints.is_empty() || !self.watches.is_empty() || self.max_steps.is_some()
+    }
+
+    // Scripts hold one command per line; blank lines and `#` comments are
+    // ignored.
+    pub fn apply_script(&mut self, src: &str) -> Result<(), String> {
+        for (idx, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.apply_command(line).map_err(|e| format!("line {}: {e}", idx + 1))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stop {
+    Halted,
+    Trapped(Trap),
+    Breakpoint(usize),
+    Watch(Watch),
+    StepLimit,
+    Debugger,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Halted => write!(f, "halted"),
+            Stop::Trapped(trap) => write!(f, "{trap}"),
+            Stop::Breakpoint(pc) => write!(f, "breakpoint at {pc}"),
+            Stop::Watch(w) => write!(f, "watch `{w}` triggered"),
+            Stop::StepLimit => write!(f, "step limit reached"),
+            Stop::Debugger => write!(f, "debugger closed"),
+        }
+    }
+}
+
+impl AssembunnyVM {
+    // Runs until the program halts or a breakpoint, watch or the step limit
+    // in `config` stops it. Breakpoints are checked before the instruction
+    // at that address runs, so calling this again resumes past it.
+    pub fn run_headless(&mut self, config: &RunConfig) -> (Stop, usize) {
+        let mut steps = 0;
+        loop {
+            if self.shell.vm.pc >= self.shell.vm.program.len() {
+                return (Stop::Halted, steps);
+            }
+            if config.max_steps.is_some_and(|max| steps >= max) {
+                return (Stop::StepLimit, steps);
+            }
+            match self.step() {
+                RunResult::Ok => {},
+                RunResult::Halt => { return (Stop::Halted, steps + 1); },
+                RunResult::Err => { return (Stop::Trapped(self.trap().unwrap()), steps); },
+                RunResult::Break => {},
+            }
+            steps += 1;
+            if let Some(w) = config.watches.iter().find(|w| w.holds(self)) {
+                return (Stop::Watch(*w), steps);
+            }
+            if config.breakpoints.contains(&self.shell.vm.pc) {
+                return (Stop::Breakpoint(self.shell.vm.pc), steps);
+            }
+        }
+    }
+
+    // Applies the register overrides in `config`, then either opens the
+    // debugger (if it was asked for) or runs headless. Anything other than a
+    // normal halt is reported on stderr, as is the profile if one was
+    // requested.
+    pub fn run_configured(&mut self, config: &RunConfig) -> Stop {
+        for (r, v) in &config.registers {
+            self.set_reg(*r, *v);
+        }
+        self.set_arith(config.arith);
+        if config.debug {
+            let _ = Debugger::run(&mut self.shell, &self.cpu);
+            return Stop::Debugger;
+        }
+        if config.profile {
+            self.enable_profiling();
+        }
+        let (stop, steps) = self.run_headless(config);
+        if let Some(profile) = self.profile() {
+            eprint!("{}", profile.listing(&self.shell.vm.program));
+        }
+        if stop != Stop::Halted {
+            let regs = self.state().registers.iter()
+                .map(|(r, v)| format!("{r}={v}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+            eprintln!("Stopped after {steps} steps at pc {}: {stop} [{regs}]", self.shell.vm.pc);
+        }
+        stop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::{Dialect, parse_program};
+
+    #[test]
+    fn headless_test() {
+        let program = parse_program(
+            "cpy 5 a\n\
+             inc b\n\
+             dec a\n\
+             jnz a -2\n",
+            Dialect::BASIC,
+        ).unwrap();
+        let mut config = RunConfig::default();
+        config.apply_script(
+            "# stop on the third pass through the loop\n\
+             watch b == 3\n\
+             break 3\n"
+        ).unwrap();
+
+        let mut vm = AssembunnyVM::new(&program, Dialect::BASIC);
+        assert_eq!(vm.run_headless(&config), (Stop::Breakpoint(3), 3));
+        config.breakpoints.clear();
+        assert_eq!(vm.run_headless(&config), (Stop::Watch("b == 3".parse().unwrap()), 5));
+        config.watches.clear();
+        config.max_steps = Some(4);
+        assert_eq!(vm.run_headless(&config), (Stop::StepLimit, 4));
+        assert_eq!(vm.run_headless(&RunConfig::default()), (Stop::Halted, 4));
+        assert_eq!(vm.get_reg('b'), 5);
+
+        assert_eq!(config.apply_script("limit\n").unwrap_err(), "line 1: `` is not a step count");
+        assert_eq!(config.apply_command("watch a = 1").unwrap_err(), "unknown comparison `=`");
+    }
+
+    #[test]
+    fn run_big_test() {
+        let program = parse_program(
+            "cpy a b\n\
+             cpy a c\n\
+             inc d\n\
+             dec c\n\
+             jnz c -2\n\
+             dec b\n\
+             jnz b -5\n",
+            Dialect::BASIC,
+        ).unwrap();
+        let mut config = RunConfig::default();
+        config.apply_command("arith big").unwrap();
+        assert_eq!(config.arith, Arith::Big);
+        let vm = config.run_big(&program, Dialect::BASIC, &[('a'.into(), 1 << 40)]).unwrap();
+        assert_eq!(vm.get_reg('d'), BigInt::from(1i64 << 40) * BigInt::from(1i64 << 40));
+
+        // the same square overflows an i64
+        let mut vm = AssembunnyVM::new(&program, Dialect::BASIC);
+        vm.optimise();
+        vm.set_reg('a', 1 << 40);
+        assert_eq!(vm.run_configured(&config), Stop::Trapped(Trap::Overflow));
+
+        config.debug = true;
+        assert!(config.run_big(&program, Dialect::BASIC, &[]).is_err());
+        assert_eq!(config.apply_command("arith huge").unwrap_err(),
+            "unknown arithmetic `huge`, expected wrapping, checked or big");
+    }
+}
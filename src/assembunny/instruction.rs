@@ -0,0 +1,324 @@
+use std::num::IntErrorKind;
+use std::str::FromStr;
+use ya_advent_lib::vm_display::{InstructionDisplay, Formatter, Token};
+use super::error::{ParseError, ParseErrorKind};
+use super::register::{Reg, RegisterSet};
+
+// Which of the optional opcodes a given day's program may use, and which
+// registers it may name. Day 12 only has cpy/inc/dec/jnz, day 23 adds tgl,
+// and day 25 adds out; all three allow any letter as a register.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Dialect {
+    pub tgl: bool,
+    pub out: bool,
+    pub registers: RegisterSet,
+}
+
+impl Dialect {
+    pub const BASIC: Dialect = Dialect { tgl: false, out: false, registers: RegisterSet::LETTERS };
+    pub const TOGGLE: Dialect = Dialect { tgl: true, out: false, registers: RegisterSet::LETTERS };
+    pub const FULL: Dialect = Dialect { tgl: true, out: true, registers: RegisterSet::LETTERS };
+
+    pub fn with_registers(self, registers: RegisterSet) -> Self {
+        Self { registers, ..self }
+    }
+
+    pub fn allows(&self, i: &Instruction) -> bool {
+        match i {
+            Instruction::Tgl(_) => self.tgl,
+            Instruction::Out(_) => self.out,
+            _ => true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub enum RI {
+    Reg(Reg),
+    Imm(i64),
+}
+
+impl FromStr for RI {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(r) = s.parse::<Reg>() {
+            return Ok(RI::Reg(r));
+        }
+        match s.parse::<i64>() {
+            Ok(v) => Ok(RI::Imm(v)),
+            Err(e) => match e.kind() {
+                IntErrorKind::PosOverflow | IntErrorKind::NegOverflow =>
+                    Err(ParseError::new(ParseErrorKind::ImmediateOutOfRange, s)),
+                _ => Err(ParseError::new(ParseErrorKind::ExpectedOperand, s)),
+            },
+        }
+    }
+}
+impl InstructionDisplay<i64> for RI {
+    fn fmt(&self, fmt: &mut Formatter<i64>) {
+        match self {
+            RI::Reg(c) => fmt.add_register(c.to_string()),
+            RI::Imm(i) => fmt.add_integer(*i),
+        };
+    }
+}
+
+// The Inv* variants are what `tgl` produces when the result would not be a
+// valid instruction (e.g. `cpy` with an immediate destination); they are
+// skipped when executed but can be toggled back. In source form they are
+// written with a `*` in front of the opcode, e.g. `*cpy 1 2`.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub enum Instruction {
+    Cpy(RI,Reg),
+    InvCpy(RI,RI),
+    Inc(Reg),
+    InvInc(RI),
+    Dec(Reg),
+    InvDec(RI),
+    Jnz(RI,RI),
+    Tgl(RI),
+    Out(Reg),
+    // Pseudo-instructions produced by the optimiser. Each one stands in for
+    // the loop starting at its own slot and keeps the instruction it replaced
+    // so the loop can be restored if `tgl` touches it.
+    Add(Reg, Reg, Box<Instruction>),
+    Mul(Reg, RI, Reg, Reg, Box<Instruction>),
+}
+
+// The operand that made a toggled instruction invalid is always an
+// immediate where a register belongs.
+fn parse_imm(s: &str) -> Result<RI, ParseError> {
+    match s.parse::<RI>()? {
+        RI::Imm(i) => Ok(RI::Imm(i)),
+        RI::Reg(_) => Err(ParseError::new(ParseErrorKind::ExpectedImmediate, s)),
+    }
+}
+
+impl FromStr for Instruction {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut iter = s.split_whitespace();
+        let opcode = iter.next()
+            .ok_or_else(|| ParseError::new(ParseErrorKind::MissingOpcode, ""))?;
+        let args: Vec<&str> = iter.collect();
+        let expected = match opcode {
+            "cpy" | "jnz" | "*cpy" => 2,
+            "inc" | "dec" | "tgl" | "out" | "*inc" | "*dec" => 1,
+            _ => { return Err(ParseError::new(ParseErrorKind::UnknownOpcode, opcode)); },
+        };
+        if args.len() != expected {
+            return Err(ParseError::new(
+                ParseErrorKind::OperandCount { expected, found: args.len() },
+                opcode,
+            ));
+        }
+        match opcode {
+            "cpy" => Ok(Instruction::Cpy(args[0].parse()?, args[1].parse()?)),
+            "inc" => Ok(Instruction::Inc(args[0].parse()?)),
+            "dec" => Ok(Instruction::Dec(args[0].parse()?)),
+            "jnz" => Ok(Instruction::Jnz(args[0].parse()?, args[1].parse()?)),
+            "tgl" => Ok(Instruction::Tgl(args[0].parse()?)),
+            "out" => Ok(Instruction::Out(args[0].parse()?)),
+            "*cpy" => Ok(Instruction::InvCpy(args[0].parse()?, parse_imm(args[1])?)),
+            "*inc" => Ok(Instruction::InvInc(parse_imm(args[0])?)),
+            "*dec" => Ok(Instruction::InvDec(parse_imm(args[0])?)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Parses a whole program, one instruction per line, rejecting any opcode or
+// register the dialect doesn't have. Blank lines are ignored but still
+// counted so that error line numbers match the source.
+pub fn parse_program(src: &str, dialect: Dialect) -> Result<Vec<Instruction>, ParseError> {
+    src.lines()
+        .enumerate()
+        .filter(|(_, l)| !l.trim().is_empty())
+        .map(|(idx, l)| {
+            let inst = l.parse::<Instruction>().map_err(|e| e.at_line(idx + 1))?;
+            if !dialect.allows(&inst) {
+                return Err(ParseError::new(ParseErrorKind::NotInDialect, inst.opcode()).at_line(idx + 1));
+            }
+            if let Some(r) = inst.registers().into_iter().find(|r| !dialect.registers.contains(*r)) {
+                return Err(ParseError::new(ParseErrorKind::UnknownRegister, &r.to_string()).at_line(idx + 1));
+            }
+            Ok(inst)
+        })
+        .collect()
+}
+
+impl Instruction {
+    pub fn opcode(&self) -> &'static str {
+        match self {
+            Instruction::Cpy(..) | Instruction::InvCpy(..) => "cpy",
+            Instruction::Inc(_) | Instruction::InvInc(_) => "inc",
+            Instruction::Dec(_) | Instruction::InvDec(_) => "dec",
+            Instruction::Jnz(..) => "jnz",
+            Instruction::Tgl(_) => "tgl",
+            Instruction::Out(_) => "out",
+            Instruction::Add(..) => "add",
+            Instruction::Mul(..) => "mul",
+        }
+    }
+
+    // The instruction as the debugger shows it, e.g. `jnz c -2`.
+    pub fn render(&self) -> String {
+        let mut fmt = Formatter::new();
+        InstructionDisplay::fmt(self, &mut fmt);
+        fmt.get_tokens().iter()
+            .map(|t| match t {
+                Token::Opcode(s) | Token::Register(s) => s.clone(),
+                Token::Integer(i) | Token::Address(i) => i.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // Number of program slots covered by this instruction.
+    pub fn fused_len(&self) -> usize {
+        match self {
+            Instruction::Add(..) => 3,
+            Instruction::Mul(..) => 6,
+            _ => 1,
+        }
+    }
+
+    // Every register the instruction names, in operand order.
+    pub fn registers(&self) -> Vec<Reg> {
+        let ri = |x: &RI| match x {
+            RI::Reg(r) => Some(*r),
+            RI::Imm(_) => None,
+        };
+        match self {
+            Instruction::Cpy(x, y) => ri(x).into_iter().chain([*y]).collect(),
+            Instruction::InvCpy(x, y) |
+            Instruction::Jnz(x, y) => ri(x).into_iter().chain(ri(y)).collect(),
+            Instruction::Inc(x) | Instruction::Dec(x) | Instruction::Out(x) => vec![*x],
+            Instruction::InvInc(x) | Instruction::InvDec(x) | Instruction::Tgl(x) => ri(x).into_iter().collect(),
+            Instruction::Add(dst, src, _) => vec![*dst, *src],
+            Instruction::Mul(dst, factor, inner, outer, _) =>
+                [Some(*dst), ri(factor), Some(*inner), Some(*outer)].into_iter().flatten().collect(),
+        }
+    }
+
+    pub fn original(&self) -> &Instruction {
+        match self {
+            Instruction::Add(_, _, orig) |
+            Instruction::Mul(_, _, _, _, orig) => orig,
+            _ => self,
+        }
+    }
+
+    pub fn toggled(&self) -> Self {
+        match self {
+            Instruction::Inc(x) => Instruction::Dec(*x),
+            Instruction::InvInc(x) => Instruction::InvDec(*x),
+            Instruction::Dec(x) => Instruction::Inc(*x),
+            Instruction::InvDec(x) => Instruction::InvInc(*x),
+            Instruction::Tgl(RI::Reg(x)) => Instruction::Inc(*x),
+            Instruction::Tgl(RI::Imm(x)) => Instruction::InvInc(RI::Imm(*x)),
+            Instruction::Jnz(x, RI::Reg(y)) => Instruction::Cpy(*x, *y),
+            Instruction::Jnz(x, RI::Imm(y)) => Instruction::InvCpy(*x, RI::Imm(*y)),
+            Instruction::Cpy(x, y) => Instruction::Jnz(*x, RI::Reg(*y)),
+            Instruction::InvCpy(x, y) => Instruction::Jnz(*x, *y),
+            Instruction::Out(x) => Instruction::Inc(*x),
+            Instruction::Add(_, _, orig) |
+            Instruction::Mul(_, _, _, _, orig) => orig.toggled(),
+        }
+    }
+}
+
+impl InstructionDisplay<i64> for Instruction {
+    fn fmt(&self, fmt: &mut Formatter<i64>) {
+        match self {
+            Instruction::Cpy(x,y) => {
+                fmt.add_opcode("cpy".into());
+                x.fmt(fmt);
+                fmt.add_register(y.to_string());
+            },
+            Instruction::InvCpy(x,y) => {
+                fmt.add_opcode("*cpy".into());
+                x.fmt(fmt);
+                y.fmt(fmt);
+            },
+            Instruction::Inc(x) => {
+                fmt.add_opcode("inc".into());
+                fmt.add_register(x.to_string());
+            },
+            Instruction::InvInc(x) => {
+                fmt.add_opcode("inc".into());
+                x.fmt(fmt);
+            },
+            Instruction::Dec(x) => {
+                fmt.add_opcode("dec".into());
+                fmt.add_register(x.to_string());
+            },
+            Instruction::InvDec(x) => {
+                fmt.add_opcode("dec".into());
+                x.fmt(fmt);
+            },
+            Instruction::Jnz(x,y) => {
+                fmt.add_opcode("jnz".into());
+                x.fmt(fmt);
+                y.fmt(fmt);
+            },
+            Instruction::Tgl(x) => {
+                fmt.add_opcode("tgl".into());
+                x.fmt(fmt);
+            },
+            Instruction::Out(x) => {
+                fmt.add_opcode("out".into());
+                fmt.add_register(x.to_string());
+            },
+            Instruction::Add(dst, src, _) => {
+                fmt.add_opcode("add".into());
+                fmt.add_register(src.to_string());
+                fmt.add_register(dst.to_string());
+            },
+            Instruction::Mul(dst, factor, inner, outer, _) => {
+                fmt.add_opcode("mul".into());
+                factor.fmt(fmt);
+                fmt.add_register(outer.to_string());
+                fmt.add_register(dst.to_string());
+                fmt.add_register(inner.to_string());
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_error_test() {
+        let err = |src| parse_program(src, Dialect::BASIC).unwrap_err().to_string();
+        assert_eq!(err("inc a\ncpy a\n"), "line 2: cpy needs 2 operands, found 1");
+        assert_eq!(err("jnz x foo\n"), "line 1: expected a register or integer, found `foo`");
+        assert_eq!(err("inc a\n\nmul a b\n"), "line 3: unknown opcode `mul`");
+        assert_eq!(err("cpy 99999999999999999999 a\n"), "line 1: immediate `99999999999999999999` out of range");
+        assert_eq!(err("cpy 1 2\n"), "line 1: expected a register, found `2`");
+        assert_eq!(err("tgl a\n"), "line 1: `tgl` is not available in this dialect");
+        assert!(parse_program("tgl a\n", Dialect::TOGGLE).is_ok());
+    }
+
+    #[test]
+    fn register_set_test() {
+        let dialect = Dialect::BASIC.with_registers(RegisterSet::named("abcd"));
+        assert!(parse_program("cpy 1 d\n", dialect).is_ok());
+        assert_eq!(parse_program("inc a\ncpy 1 e\n", dialect).unwrap_err().to_string(),
+            "line 2: register `e` is not in this register set");
+        assert_eq!(parse_program("inc r0\n", dialect).unwrap_err().to_string(),
+            "line 1: register `r0` is not in this register set");
+
+        let dialect = Dialect::BASIC.with_registers(RegisterSet::numbered(16));
+        let program = parse_program("cpy 41 r15\ninc r15\njnz r0 -1\n", dialect).unwrap();
+        assert_eq!(program[0], Instruction::Cpy(RI::Imm(41), Reg::Numbered(15)));
+        assert_eq!(parse_program("inc r16\n", dialect).unwrap_err().to_string(),
+            "line 1: register `r16` is not in this register set");
+        assert_eq!(parse_program("inc a\n", dialect).unwrap_err().to_string(),
+            "line 1: register `a` is not in this register set");
+        assert_eq!(parse_program("inc r256\n", dialect).unwrap_err().to_string(),
+            "line 1: expected a register, found `r256`");
+    }
+}
@@ -0,0 +1,97 @@
+use ya_advent_lib::vm_shell::RunResult;
+use super::arith::Trap;
+use super::cpu::AssembunnyVM;
+use super::register::Reg;
+use super::state::LoopDetector;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunStatus {
+    Halted,
+    Trapped(Trap),
+    StepLimitReached,
+    LoopDetected,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RunOutcome {
+    pub status: RunStatus,
+    pub steps: usize,
+    pub registers: Vec<(Reg, i64)>,
+}
+
+impl AssembunnyVM {
+    // Runs for at most `max_steps` instructions. Stops early if the machine
+    // returns to a state it has already been in (pc, registers and the
+    // program as rewritten by `tgl`), since it can then never halt.
+    // `steps` in the outcome counts the instructions run by this call.
+    pub fn run_with_limit(&mut self, max_steps: usize) -> RunOutcome {
+        let start = self.steps;
+        let mut detector = LoopDetector::new();
+        let status = loop {
+            if self.steps - start >= max_steps {
+                break RunStatus::StepLimitReached;
+            }
+            if detector.check(&self.shell.vm) {
+                break RunStatus::LoopDetected;
+            }
+            match self.step() {
+                RunResult::Ok | RunResult::Break => {},
+                RunResult::Halt => { break RunStatus::Halted; },
+                RunResult::Err => { break RunStatus::Trapped(self.trap().unwrap()); },
+            }
+        };
+        RunOutcome {
+            status,
+            steps: self.steps - start,
+            registers: self.state().registers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::{Dialect, parse_program};
+
+    #[test]
+    fn limit_test() {
+        let program = parse_program(
+            "cpy 3 a\n\
+             dec a\n\
+             jnz a -1\n\
+             jnz c -3\n",
+            Dialect::BASIC,
+        ).unwrap();
+        let mut vm = AssembunnyVM::new(&program, Dialect::BASIC);
+        let outcome = vm.run_with_limit(100);
+        assert_eq!(outcome.status, RunStatus::Halted);
+        assert_eq!(outcome.steps, 8);
+        assert_eq!(outcome.registers, vec![]);
+
+        let mut vm = AssembunnyVM::new(&program, Dialect::BASIC);
+        assert_eq!(vm.run_with_limit(5).status, RunStatus::StepLimitReached);
+        assert_eq!(vm.get_reg('a'), 1);
+
+        let mut vm = AssembunnyVM::new(&program, Dialect::BASIC);
+        vm.set_reg('c', 1);
+        let outcome = vm.run_with_limit(1_000_000);
+        assert_eq!(outcome.status, RunStatus::LoopDetected);
+        assert_eq!(outcome.registers, vec![('c'.into(), 1)]);
+        assert!(outcome.steps < 100);
+
+        // Coming back to pc 0 with the same registers is not a loop if tgl
+        // has changed the program in between.
+        let program = parse_program(
+            "jnz 1 2\n\
+             jnz 1 3\n\
+             tgl a\n\
+             jnz 1 -3\n",
+            Dialect::TOGGLE,
+        ).unwrap();
+        let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        vm.set_reg('a', -2);
+        let outcome = vm.run_with_limit(1000);
+        assert_eq!(outcome.status, RunStatus::Halted);
+        assert_eq!(outcome.steps, 5);
+    }
+}
@@ -0,0 +1,40 @@
+mod arith;
+mod cli;
+mod clock;
+mod compiled;
+mod cpu;
+mod decompile;
+mod error;
+mod fuzz;
+mod headless;
+mod instruction;
+mod limit;
+mod optimise;
+mod printer;
+mod profile;
+mod register;
+mod snapshot;
+mod state;
+mod symbolic;
+#[cfg(test)]
+mod test_programs;
+
+pub use arith::{Arith, Trap, Word};
+pub use cli::{Action, load};
+pub use clock::{find_clock_signal, is_clock_signal};
+pub use compiled::CompiledVM;
+pub use cpu::{AssembunnyCPU, AssembunnyVM};
+pub use decompile::decompile;
+pub use error::{ParseError, ParseErrorKind};
+pub use fuzz::{BACKENDS, Backend, Disagreement, Outcome, Rng, check, fuzz, random_program, run_on, save_case, shrink};
+pub use headless::{Cmp, RunConfig, Stop, Watch};
+pub use instruction::{Dialect, Instruction, RI, parse_program};
+pub use limit::{RunOutcome, RunStatus};
+pub use optimise::optimise;
+pub use printer::{diff_programs, write_program};
+pub use profile::Profile;
+pub use register::{Reg, RegisterSet};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use state::MachineState;
+pub use symbolic::{Analysis, Expr, SymbolicError, analyse};
+pub use num_bigint::BigInt;
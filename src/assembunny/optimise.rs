@@ -0,0 +1,149 @@
+use super::instruction::{Instruction, RI};
+use super::register::Reg;
+
+// Recognises the two loop idioms assembunny uses in place of arithmetic:
+//
+//   inc a          cpy b c
+//   dec c          inc a
+//   jnz c -2       dec c
+//                  jnz c -2
+//                  dec d
+//                  jnz d -5
+//
+// (the inc/dec pair may come in either order), and replaces the first slot
+// of each with a fused Add or Mul. The remaining slots are left untouched so
+// that jumps into the middle of a loop still behave.
+fn fuse_at(program: &[Instruction], idx: usize) -> Option<Instruction> {
+    let head = program[idx].original();
+    if let Some((dst, factor, inner, outer)) = match_mul(program, idx) {
+        return Some(Instruction::Mul(dst, factor, inner, outer, Box::new(head.clone())));
+    }
+    if let Some((dst, src)) = match_add(program, idx) {
+        return Some(Instruction::Add(dst, src, Box::new(head.clone())));
+    }
+    None
+}
+
+pub(crate) fn match_add(program: &[Instruction], idx: usize) -> Option<(Reg, Reg)> {
+    let slots = program.get(idx..idx + 3)?;
+    let (dst, src) = match (slots[0].original(), slots[1].original()) {
+        (Instruction::Inc(a), Instruction::Dec(c)) |
+        (Instruction::Dec(c), Instruction::Inc(a)) => (*a, *c),
+        _ => { return None; },
+    };
+    match slots[2].original() {
+        Instruction::Jnz(RI::Reg(c), RI::Imm(-2)) if *c == src && dst != src => Some((dst, src)),
+        _ => None,
+    }
+}
+
+pub(crate) fn match_mul(program: &[Instruction], idx: usize) -> Option<(Reg, RI, Reg, Reg)> {
+    let slots = program.get(idx..idx + 6)?;
+    let (factor, inner) = match slots[0].original() {
+        Instruction::Cpy(b, c) => (*b, *c),
+        _ => { return None; },
+    };
+    let (dst, src) = match_add(program, idx + 1)?;
+    if src != inner { return None; }
+    let outer = match slots[4].original() {
+        Instruction::Dec(d) => *d,
+        _ => { return None; },
+    };
+    match slots[5].original() {
+        Instruction::Jnz(RI::Reg(d), RI::Imm(-5)) if *d == outer => {},
+        _ => { return None; },
+    }
+    if outer == dst || outer == inner { return None; }
+    if let RI::Reg(b) = factor {
+        if b == dst || b == inner || b == outer { return None; }
+    }
+    Some((dst, factor, inner, outer))
+}
+
+pub fn optimise(program: &[Instruction]) -> Vec<Instruction> {
+    (0..program.len())
+        .map(|idx| fuse_at(program, idx).unwrap_or_else(|| program[idx].clone()))
+        .collect()
+}
+
+// Called before `tgl` rewrites slot `loc`: any fused instruction whose
+// region covers it is put back to the original loop head.
+pub(crate) fn unfuse_around(program: &mut [Instruction], loc: usize) {
+    let start = loc.saturating_sub(5);
+    for (idx, inst) in program[start..=loc].iter_mut().enumerate() {
+        if start + idx + inst.fused_len() > loc && inst.fused_len() > 1 {
+            *inst = inst.original().clone();
+        }
+    }
+}
+
+// Called after `tgl` rewrote slot `loc`, in case the toggle created a new
+// loop idiom.
+pub(crate) fn fuse_around(program: &mut [Instruction], loc: usize) {
+    for idx in loc.saturating_sub(5)..=loc {
+        if let Some(fused) = fuse_at(program, idx) {
+            program[idx] = fused;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::{AssembunnyVM, Dialect, parse_program};
+    use Reg::Named;
+    use crate::assembunny::test_programs::DAY23;
+
+    #[test]
+    fn optimise_test() {
+        let program = parse_program(DAY23, Dialect::TOGGLE).unwrap();
+        let optimised = optimise(&program);
+        assert!(matches!(optimised[4], Instruction::Mul(Named('a'), RI::Reg(Named('b')), Named('c'), Named('d'), _)));
+        assert!(matches!(optimised[5], Instruction::Add(Named('a'), Named('c'), _)));
+        assert!(matches!(optimised[13], Instruction::Add(Named('c'), Named('d'), _)));
+        assert_eq!(optimised.iter().filter(|i| i.fused_len() > 1).count(), 3);
+
+        let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        vm.set_reg('a', 7);
+        vm.run();
+        let mut opt = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        opt.optimise();
+        opt.set_reg('a', 7);
+        opt.run();
+        assert_eq!(vm.get_reg('a'), 5040 + 73 * 79);
+        assert_eq!(opt.get_reg('a'), vm.get_reg('a'));
+        // The toggles turn `inc a / inc d / jnz d -2` into an add loop.
+        assert!(matches!(opt.shell.vm.program[21], Instruction::Add(Named('a'), Named('d'), _)));
+
+        let mut opt = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        opt.optimise();
+        opt.set_reg('a', 12);
+        opt.run();
+        assert_eq!(opt.get_reg('a'), 479001600 + 73 * 79);
+    }
+
+    #[test]
+    fn toggle_in_fused_region_test() {
+        let program = parse_program(
+            "cpy 3 c\n\
+             tgl d\n\
+             inc a\n\
+             dec c\n\
+             jnz c -2\n",
+            Dialect::TOGGLE,
+        ).unwrap();
+        let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        vm.optimise();
+        vm.run();
+        assert_eq!(vm.get_reg('a'), 3);
+        assert!(matches!(vm.shell.vm.program[2], Instruction::Add(Named('a'), Named('c'), _)));
+
+        // Toggling the `inc a` inside the fused loop has to undo the fusion.
+        let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        vm.optimise();
+        vm.set_reg('d', 1);
+        vm.run();
+        assert_eq!(vm.get_reg('a'), -3);
+        assert_eq!(vm.shell.vm.program[2], Instruction::Dec(Named('a')));
+    }
+}
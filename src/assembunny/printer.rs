@@ -0,0 +1,85 @@
+use std::fmt;
+use super::instruction::{Instruction, RI};
+
+impl fmt::Display for RI {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RI::Reg(r) => write!(f, "{r}"),
+            RI::Imm(i) => write!(f, "{i}"),
+        }
+    }
+}
+
+// Writes the instruction as assembunny source that `Instruction::from_str`
+// reads back unchanged. Instructions that `tgl` made invalid get a `*` in
+// front of the opcode; fused instructions are written as the loop head they
+// replaced.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Cpy(x, y) => write!(f, "cpy {x} {y}"),
+            Instruction::InvCpy(x, y) => write!(f, "*cpy {x} {y}"),
+            Instruction::Inc(x) => write!(f, "inc {x}"),
+            Instruction::InvInc(x) => write!(f, "*inc {x}"),
+            Instruction::Dec(x) => write!(f, "dec {x}"),
+            Instruction::InvDec(x) => write!(f, "*dec {x}"),
+            Instruction::Jnz(x, y) => write!(f, "jnz {x} {y}"),
+            Instruction::Tgl(x) => write!(f, "tgl {x}"),
+            Instruction::Out(x) => write!(f, "out {x}"),
+            Instruction::Add(..) |
+            Instruction::Mul(..) => self.original().fmt(f),
+        }
+    }
+}
+
+pub fn write_program(program: &[Instruction]) -> String {
+    program.iter()
+        .map(|i| format!("{i}\n"))
+        .collect()
+}
+
+// Lists the slots where two versions of a program differ, e.g. the input
+// and a snapshot taken after some toggles have run.
+pub fn diff_programs(before: &[Instruction], after: &[Instruction]) -> String {
+    before.iter()
+        .zip(after.iter())
+        .enumerate()
+        .filter(|(_, (a, b))| a.original() != b.original())
+        .map(|(pc, (a, b))| format!("{pc:>4}: {a} -> {b}\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::{AssembunnyVM, Dialect, parse_program};
+
+    #[test]
+    fn round_trip_test() {
+        let src = "cpy 2 a\n\
+                   tgl a\n\
+                   tgl a\n\
+                   tgl a\n\
+                   cpy 1 a\n\
+                   dec a\n\
+                   dec a\n\
+                   jnz 1 2\n\
+                   tgl 1\n\
+                   tgl -2\n";
+        let program = parse_program(src, Dialect::TOGGLE).unwrap();
+        assert_eq!(write_program(&program), src);
+
+        let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        vm.run();
+        let snapshot = write_program(&vm.shell.vm.program);
+        let reread = parse_program(&snapshot, Dialect::TOGGLE).unwrap();
+        assert_eq!(reread, vm.shell.vm.program);
+        assert_eq!(diff_programs(&program, &reread),
+            "   3: tgl a -> inc a\n\
+            \x20  4: cpy 1 a -> jnz 1 a\n\
+            \x20  7: jnz 1 2 -> *cpy 1 2\n");
+        assert!(snapshot.contains("*cpy 1 2\n"));
+        assert_eq!("*cpy 1 a".parse::<Instruction>().unwrap_err().to_string(),
+            "expected an integer in a skipped instruction, found `a`");
+    }
+}
@@ -0,0 +1,149 @@
+use ya_advent_lib::vm_shell::InstructionResult;
+use super::instruction::Instruction;
+
+// Per-slot counters collected while profiling. `toggled` counts how often
+// `tgl` rewrote a slot, indexed by the slot being rewritten.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Profile {
+    pub executed: Vec<usize>,
+    pub taken: Vec<usize>,
+    pub not_taken: Vec<usize>,
+    pub toggled: Vec<usize>,
+}
+
+impl Profile {
+    pub fn new(len: usize) -> Self {
+        Self {
+            executed: vec![0; len],
+            taken: vec![0; len],
+            not_taken: vec![0; len],
+            toggled: vec![0; len],
+        }
+    }
+
+    pub(crate) fn record(&mut self, pc: usize, i: &Instruction, r: InstructionResult) {
+        self.executed[pc] += 1;
+        if let Instruction::Jnz(..) = i {
+            match r {
+                InstructionResult::JumpFwd(_) | InstructionResult::JumpBck(_) => { self.taken[pc] += 1; },
+                _ => { self.not_taken[pc] += 1; },
+            }
+        }
+    }
+
+    // A fused instruction runs a whole loop in one go. Credit each slot it
+    // covers with what the unfused loop would have done: `inner` passes of
+    // the inc/dec/jnz loop for each of the `outer` passes round a `Mul`.
+    pub(crate) fn record_loop(&mut self, pc: usize, i: &Instruction, inner: usize, outer: usize) {
+        let mut add_loop = |at: usize, passes: usize| {
+            for slot in at..at + 3 {
+                self.executed[slot] += inner * passes;
+            }
+            self.taken[at + 2] += (inner - 1) * passes;
+            self.not_taken[at + 2] += passes;
+        };
+        match i {
+            Instruction::Add(..) => { add_loop(pc, 1); },
+            Instruction::Mul(..) => {
+                add_loop(pc + 1, outer);
+                self.executed[pc] += outer;
+                self.executed[pc + 4] += outer;
+                self.executed[pc + 5] += outer;
+                self.taken[pc + 5] += outer - 1;
+                self.not_taken[pc + 5] += 1;
+            },
+            _ => {},
+        }
+    }
+
+    // The program annotated with the counters, one slot per line, with a bar
+    // showing each slot's share of all executed instructions.
+    pub fn listing(&self, program: &[Instruction]) -> String {
+        const BAR: usize = 20;
+        let total: usize = self.executed.iter().sum();
+        let mut out = format!(
+            "{:>4} {:>12} {:>12} {:>12} {:>8}  {:<BAR$}  instruction\n",
+            "pc", "executed", "taken", "not taken", "toggled", "share",
+        );
+        let blank = |n: usize| if n == 0 { String::new() } else { n.to_string() };
+        for (pc, inst) in program.iter().enumerate() {
+            let bar = (self.executed[pc] * BAR).checked_div(total).unwrap_or(0);
+            out.push_str(&format!(
+                "{pc:>4} {:>12} {:>12} {:>12} {:>8}  {:<BAR$}  {}\n",
+                self.executed[pc],
+                blank(self.taken[pc]),
+                blank(self.not_taken[pc]),
+                blank(self.toggled[pc]),
+                "#".repeat(bar),
+                inst.render(),
+            ));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::assembunny::{AssembunnyVM, Dialect, parse_program};
+
+    #[test]
+    fn profile_test() {
+        let program = parse_program(
+            "cpy 4 a\n\
+             tgl a\n\
+             dec a\n\
+             jnz a -2\n\
+             inc b\n",
+            Dialect::TOGGLE,
+        ).unwrap();
+        let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        vm.enable_profiling();
+        vm.run();
+        let profile = vm.profile().unwrap();
+        // The second toggle turns the jnz into an invalid cpy, which ends
+        // the loop after three passes.
+        assert_eq!(profile.executed, vec![1, 3, 3, 3, 1]);
+        assert_eq!(profile.taken[3], 2);
+        assert_eq!(profile.not_taken[3], 0);
+        assert_eq!(profile.toggled, vec![0, 0, 0, 1, 1]);
+        assert_eq!(vm.get_reg('b'), -1);
+
+        let listing = profile.listing(&vm.shell.vm.program);
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[2].starts_with("   1            3"));
+        assert!(lines[2].ends_with("tgl a"));
+        assert_eq!(lines[2].matches('#').count(), 5);
+        assert!(lines[4].ends_with("*cpy a -2"));
+        assert!(lines[5].ends_with("dec b"));
+    }
+
+    #[test]
+    fn optimised_profile_test() {
+        let program = parse_program(
+            "cpy 3 d\n\
+             cpy 4 c\n\
+             inc a\n\
+             dec c\n\
+             jnz c -2\n\
+             dec d\n\
+             jnz d -5\n\
+             cpy 5 b\n\
+             inc a\n\
+             dec b\n\
+             jnz b -2\n",
+            Dialect::BASIC,
+        ).unwrap();
+        let mut plain = AssembunnyVM::new(&program, Dialect::BASIC);
+        plain.enable_profiling();
+        plain.run();
+        let mut fused = AssembunnyVM::new(&program, Dialect::BASIC);
+        fused.optimise();
+        fused.enable_profiling();
+        fused.run();
+        assert_eq!(fused.get_reg('a'), 17);
+        // the fused loops still show up as hot, slot for slot
+        assert_eq!(fused.profile(), plain.profile());
+        assert_eq!(plain.profile().unwrap().executed, vec![1, 3, 12, 12, 12, 3, 3, 1, 5, 5, 5]);
+    }
+}
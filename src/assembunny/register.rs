@@ -0,0 +1,105 @@
+use std::fmt;
+use std::str::FromStr;
+use super::error::{ParseError, ParseErrorKind};
+
+// A register name. The puzzles only ever use single letters, but programs
+// written for a larger machine can use numbered registers `r0`..`r255`.
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Reg {
+    Named(char),
+    Numbered(u8),
+}
+
+// Size of a register file that can hold every possible register, indexed by
+// `Reg::index`.
+pub(crate) const REGISTER_FILE_LEN: usize = 26 + 256;
+
+impl Reg {
+    // Letters first, then numbered registers, so index order matches the
+    // derived ordering.
+    pub(crate) fn index(self) -> usize {
+        match self {
+            Reg::Named(c) => (c as u8 - b'a') as usize,
+            Reg::Numbered(n) => 26 + n as usize,
+        }
+    }
+
+    pub(crate) fn from_index(idx: usize) -> Self {
+        if idx < 26 {
+            Reg::Named((b'a' + idx as u8) as char)
+        }
+        else {
+            Reg::Numbered((idx - 26) as u8)
+        }
+    }
+}
+
+impl From<char> for Reg {
+    fn from(c: char) -> Self {
+        Reg::Named(c)
+    }
+}
+
+// The debugger wants register keys it can print.
+impl From<Reg> for String {
+    fn from(r: Reg) -> Self {
+        r.to_string()
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reg::Named(c) => write!(f, "{c}"),
+            Reg::Numbered(n) => write!(f, "r{n}"),
+        }
+    }
+}
+
+impl FromStr for Reg {
+    type Err = ParseError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c @ 'a' ..= 'z'), None) => Ok(Reg::Named(c)),
+            (Some('r'), Some('0' ..= '9')) => s[1..].parse::<u8>()
+                .map(Reg::Numbered)
+                .map_err(|_| ParseError::new(ParseErrorKind::ExpectedRegister, s)),
+            _ => Err(ParseError::new(ParseErrorKind::ExpectedRegister, s)),
+        }
+    }
+}
+
+// Which registers a program may use: a subset of the letters, or the first
+// `n` numbered registers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RegisterSet {
+    Named(u32),
+    Numbered(u16),
+}
+
+impl RegisterSet {
+    pub const LETTERS: RegisterSet = RegisterSet::Named((1 << 26) - 1);
+
+    // e.g. `RegisterSet::named("abcd")`. Panics on anything but `a`-`z`.
+    pub fn named(names: &str) -> Self {
+        RegisterSet::Named(names.chars().fold(0, |mask, c| {
+            assert!(c.is_ascii_lowercase(), "`{c}` is not a register letter");
+            mask | 1 << (c as u8 - b'a')
+        }))
+    }
+
+    // `r0` up to `r{count - 1}`; at most 256.
+    pub fn numbered(count: u16) -> Self {
+        assert!(count <= 256, "at most 256 numbered registers");
+        RegisterSet::Numbered(count)
+    }
+
+    pub fn contains(&self, r: Reg) -> bool {
+        match (self, r) {
+            (RegisterSet::Named(mask), Reg::Named(c)) => mask & 1 << (c as u8 - b'a') != 0,
+            (RegisterSet::Numbered(count), Reg::Numbered(n)) => (n as u16) < *count,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use super::cpu::AssembunnyVM;
+use super::error::ParseError;
+use super::instruction::{Dialect, parse_program};
+use super::printer::write_program;
+use super::register::Reg;
+use super::state::MachineState;
+
+// A resumable copy of a VM, written as plain text so it can be diffed and
+// checked in as a test fixture:
+//
+//   pc 17
+//   steps 1234
+//   reg a 5040
+//   reg c -16
+//   program
+//   cpy a b
+//   ...
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Snapshot {
+    pub state: MachineState,
+    pub steps: usize,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SnapshotError {
+    Header(usize, String),
+    Program(ParseError),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Header(line, msg) => write!(f, "line {line}: {msg}"),
+            SnapshotError::Program(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pc {}", self.state.pc)?;
+        writeln!(f, "steps {}", self.steps)?;
+        for (r, v) in &self.state.registers {
+            writeln!(f, "reg {r} {v}")?;
+        }
+        writeln!(f, "program")?;
+        write!(f, "{}", write_program(&self.state.program))
+    }
+}
+
+// Parses with `Dialect::FULL`, which only has letter registers; use
+// `Snapshot::parse` for VMs with another register set.
+impl FromStr for Snapshot {
+    type Err = SnapshotError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Snapshot::parse(s, Dialect::FULL)
+    }
+}
+
+impl Snapshot {
+    // The program section is parsed in `dialect`, which should be the one
+    // the snapshot's VM was running.
+    pub fn parse(s: &str, dialect: Dialect) -> Result<Self, SnapshotError> {
+        let mut pc = None;
+        let mut steps = None;
+        let mut registers = Vec::new();
+        let mut lines = s.lines().enumerate();
+        let header_len = loop {
+            let Some((idx, line)) = lines.next() else {
+                return Err(SnapshotError::Header(s.lines().count(), "missing `program` section".into()));
+            };
+            let err = |msg: String| SnapshotError::Header(idx + 1, msg);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words[..] {
+                ["program"] => { break idx + 1; },
+                ["pc", n] => {
+                    pc = Some(n.parse::<usize>().map_err(|_| err(format!("bad pc `{n}`")))?);
+                },
+                ["steps", n] => {
+                    steps = Some(n.parse::<usize>().map_err(|_| err(format!("bad step count `{n}`")))?);
+                },
+                ["reg", r, v] => {
+                    let r = r.parse::<Reg>().map_err(|_| err(format!("bad register `{r}`")))?;
+                    let v = v.parse::<i64>().map_err(|_| err(format!("bad value `{v}`")))?;
+                    if v != 0 {
+                        registers.push((r, v));
+                    }
+                },
+                [] => {},
+                _ => { return Err(err(format!("unexpected `{line}`"))); },
+            }
+        };
+        let src: Vec<&str> = lines.map(|(_, l)| l).collect();
+        let program = parse_program(&src.join("\n"), dialect)
+            .map_err(|e| SnapshotError::Program(match e.line {
+                Some(line) => e.at_line(line + header_len),
+                None => e,
+            }))?;
+        registers.sort();
+        Ok(Snapshot {
+            state: MachineState {
+                pc: pc.ok_or_else(|| SnapshotError::Header(header_len, "missing pc".into()))?,
+                registers,
+                program,
+            },
+            steps: steps.unwrap_or(0),
+        })
+    }
+}
+
+impl AssembunnyVM {
+    // Fused instructions are stored as the loop heads they replaced, the
+    // same as the text form, so a snapshot doesn't depend on whether the VM
+    // was optimised.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut state = self.state();
+        state.program = state.program.iter().map(|i| i.original().clone()).collect();
+        Snapshot {
+            state,
+            steps: self.steps,
+        }
+    }
+
+    // Builds a VM that carries on exactly where the snapshot was taken. The
+    // program comes back unoptimised; call `optimise` again if wanted.
+    pub fn restore(snapshot: &Snapshot, dialect: Dialect) -> Self {
+        let mut vm = AssembunnyVM::new(&snapshot.state.program, dialect);
+        vm.shell.vm.pc = snapshot.state.pc;
+        for (r, v) in &snapshot.state.registers {
+            vm.set_reg(*r, *v);
+        }
+        vm.steps = snapshot.steps;
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::RegisterSet;
+
+    #[test]
+    fn snapshot_test() {
+        let program = parse_program(
+            "cpy 2 a\n\
+             tgl a\n\
+             tgl a\n\
+             tgl a\n\
+             cpy 1 a\n\
+             dec a\n\
+             dec a\n",
+            Dialect::TOGGLE,
+        ).unwrap();
+        let mut vm = AssembunnyVM::new(&program, Dialect::TOGGLE);
+        vm.optimise();
+        for _ in 0..4 {
+            vm.step();
+        }
+        let text = vm.snapshot().to_string();
+        assert_eq!(text,
+            "pc 4\n\
+             steps 4\n\
+             reg a 3\n\
+             program\n\
+             cpy 2 a\n\
+             tgl a\n\
+             tgl a\n\
+             inc a\n\
+             jnz 1 a\n\
+             dec a\n\
+             dec a\n");
+
+        let snapshot = text.parse::<Snapshot>().unwrap();
+        let mut resumed = AssembunnyVM::restore(&snapshot, Dialect::TOGGLE);
+        resumed.run();
+        vm.run();
+        assert_eq!(resumed.snapshot(), vm.snapshot());
+        assert_eq!(resumed.get_reg('a'), 3);
+
+        let bad = "pc 4\nreg a x\nprogram\n".parse::<Snapshot>().unwrap_err();
+        assert_eq!(bad.to_string(), "line 2: bad value `x`");
+        let bad = "pc 0\nprogram\ninc a\nmul a b\n".parse::<Snapshot>().unwrap_err();
+        assert_eq!(bad.to_string(), "line 4: unknown opcode `mul`");
+
+        let dialect = Dialect::BASIC.with_registers(RegisterSet::numbered(4));
+        let program = parse_program("cpy 3 r1\ndec r1\njnz r1 -1\n", dialect).unwrap();
+        let mut vm = AssembunnyVM::new(&program, dialect);
+        vm.step();
+        vm.step();
+        let text = vm.snapshot().to_string();
+        assert_eq!(text, "pc 2\nsteps 2\nreg r1 2\nprogram\ncpy 3 r1\ndec r1\njnz r1 -1\n");
+        let snapshot = Snapshot::parse(&text, dialect).unwrap();
+        let mut resumed = AssembunnyVM::restore(&snapshot, dialect);
+        resumed.run();
+        vm.run();
+        assert_eq!(resumed.snapshot(), vm.snapshot());
+        assert_eq!(text.parse::<Snapshot>().unwrap_err().to_string(),
+            "line 5: register `r1` is not in this register set");
+    }
+}
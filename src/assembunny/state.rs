@@ -0,0 +1,84 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use ya_advent_lib::vm_shell::VM;
+use super::instruction::Instruction;
+use super::register::Reg;
+
+// Everything that determines how a VM will continue from here. Registers are
+// kept sorted with zeros dropped, so an unset register and one holding 0
+// compare equal.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct MachineState {
+    pub pc: usize,
+    pub registers: Vec<(Reg, i64)>,
+    pub program: Vec<Instruction>,
+}
+
+fn sorted_registers(vm: &VM<Reg, i64, Instruction>) -> Vec<(Reg, i64)> {
+    let mut registers: Vec<(Reg, i64)> = vm.registers.iter()
+        .filter(|(_, v)| **v != 0)
+        .map(|(k, v)| (*k, *v))
+        .collect();
+    registers.sort();
+    registers
+}
+
+impl MachineState {
+    pub fn new(vm: &VM<Reg, i64, Instruction>) -> Self {
+        Self {
+            pc: vm.pc,
+            registers: sorted_registers(vm),
+            program: vm.program.clone(),
+        }
+    }
+
+    // Hash of the state `new` would build, without cloning the program.
+    pub fn fingerprint(vm: &VM<Reg, i64, Instruction>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        vm.pc.hash(&mut hasher);
+        sorted_registers(vm).hash(&mut hasher);
+        vm.program.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+// Brent's cycle detection over machine states: holds a single checkpoint,
+// moved forward at power-of-two intervals, and reports a loop once the VM
+// comes back to it. States are compared by pc and fingerprint first and
+// only compared in full when those match.
+pub(crate) struct LoopDetector {
+    checkpoint: Option<(u64, MachineState)>,
+    power: usize,
+    lam: usize,
+}
+
+impl LoopDetector {
+    pub fn new() -> Self {
+        Self { checkpoint: None, power: 1, lam: 0 }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    // Call once per step; returns true when the current state has been seen
+    // before.
+    pub fn check(&mut self, vm: &VM<Reg, i64, Instruction>) -> bool {
+        if let Some((cfp, cstate)) = &self.checkpoint {
+            if cstate.pc == vm.pc
+                && *cfp == MachineState::fingerprint(vm)
+                && *cstate == MachineState::new(vm) {
+                return true;
+            }
+        }
+        if self.checkpoint.is_none() || self.lam == self.power {
+            self.checkpoint = Some((MachineState::fingerprint(vm), MachineState::new(vm)));
+            if self.lam == self.power {
+                self.power *= 2;
+            }
+            self.lam = 0;
+        }
+        self.lam += 1;
+        false
+    }
+}
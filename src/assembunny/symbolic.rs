@@ -0,0 +1,495 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use num_bigint::{BigInt, BigUint, Sign};
+use super::instruction::{Instruction, RI};
+use super::register::Reg;
+
+// A polynomial over the registers' starting values, e.g. `a^2 - a + 5767`.
+// Each monomial is a sorted list of registers (repeated for powers) mapped
+// to a nonzero coefficient, so equal polynomials compare equal.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Expr(BTreeMap<Vec<Reg>, BigInt>);
+
+impl Expr {
+    pub fn constant(v: impl Into<BigInt>) -> Self {
+        let mut e = Expr::default();
+        e.add_term(Vec::new(), v.into());
+        e
+    }
+
+    pub fn var(r: Reg) -> Self {
+        Expr(BTreeMap::from([(vec![r], BigInt::from(1))]))
+    }
+
+    pub fn as_constant(&self) -> Option<BigInt> {
+        match self.0.len() {
+            0 => Some(BigInt::ZERO),
+            1 => self.0.get(&Vec::new()).cloned(),
+            _ => None,
+        }
+    }
+
+    pub fn vars(&self) -> BTreeSet<Reg> {
+        self.0.keys().flatten().copied().collect()
+    }
+
+    fn add_term(&mut self, mono: Vec<Reg>, c: BigInt) {
+        let sum = self.0.remove(&mono).unwrap_or_default() + c;
+        if sum != BigInt::ZERO {
+            self.0.insert(mono, sum);
+        }
+    }
+
+    // Replaces each register with its expression in `env`; registers not in
+    // `env` are left as they are.
+    pub fn subst(&self, env: &BTreeMap<Reg, Expr>) -> Expr {
+        let mut out = Expr::default();
+        for (mono, c) in &self.0 {
+            let term = mono.iter().fold(Expr::constant(c.clone()), |acc, r| match env.get(r) {
+                Some(e) => &acc * e,
+                None => &acc * &Expr::var(*r),
+            });
+            out = &out + &term;
+        }
+        out
+    }
+
+    // The value for the given starting registers; any not listed are 0.
+    pub fn evaluate(&self, values: &[(Reg, i64)]) -> BigInt {
+        let env = self.vars().into_iter()
+            .map(|r| {
+                let v = values.iter().find(|(vr, _)| *vr == r).map_or(0, |(_, v)| *v);
+                (r, Expr::constant(v))
+            })
+            .collect();
+        self.subst(&env).as_constant().unwrap()
+    }
+}
+
+impl Add for &Expr {
+    type Output = Expr;
+    fn add(self, rhs: &Expr) -> Expr {
+        let mut out = self.clone();
+        for (mono, c) in &rhs.0 {
+            out.add_term(mono.clone(), c.clone());
+        }
+        out
+    }
+}
+
+impl Neg for &Expr {
+    type Output = Expr;
+    fn neg(self) -> Expr {
+        Expr(self.0.iter().map(|(m, c)| (m.clone(), -c)).collect())
+    }
+}
+
+impl Sub for &Expr {
+    type Output = Expr;
+    fn sub(self, rhs: &Expr) -> Expr {
+        self + &-rhs
+    }
+}
+
+impl Mul for &Expr {
+    type Output = Expr;
+    fn mul(self, rhs: &Expr) -> Expr {
+        let mut out = Expr::default();
+        for (m1, c1) in &self.0 {
+            for (m2, c2) in &rhs.0 {
+                let mut mono: Vec<Reg> = m1.iter().chain(m2).copied().collect();
+                mono.sort();
+                out.add_term(mono, c1 * c2);
+            }
+        }
+        out
+    }
+}
+
+// Highest degree first, e.g. `a^2*b - 3*a + 1`.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "0");
+        }
+        let mut terms: Vec<_> = self.0.iter().collect();
+        terms.sort_by(|(m1, _), (m2, _)| m2.len().cmp(&m1.len()).then(m1.cmp(m2)));
+        for (idx, (mono, c)) in terms.into_iter().enumerate() {
+            let neg = c.sign() == Sign::Minus;
+            match (idx, neg) {
+                (0, true) => write!(f, "-")?,
+                (0, false) => {},
+                (_, true) => write!(f, " - ")?,
+                (_, false) => write!(f, " + ")?,
+            }
+            let mut powers: Vec<(Reg, usize)> = Vec::new();
+            for r in mono {
+                match powers.last_mut() {
+                    Some((pr, n)) if pr == r => { *n += 1; },
+                    _ => { powers.push((*r, 1)); },
+                }
+            }
+            let vars = powers.iter()
+                .map(|(r, n)| if *n == 1 { r.to_string() } else { format!("{r}^{n}") })
+                .collect::<Vec<_>>()
+                .join("*");
+            let mag = c.magnitude();
+            if vars.is_empty() {
+                write!(f, "{mag}")?;
+            }
+            else if *mag == BigUint::from(1u32) {
+                write!(f, "{vars}")?;
+            }
+            else {
+                write!(f, "{mag}*{vars}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SymbolicError {
+    Branch { pc: usize, condition: Expr },
+    ComputedJump { pc: usize, offset: Expr },
+    ComputedToggle { pc: usize, offset: Expr },
+    StepLimit(usize),
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SymbolicError::Branch { pc, condition } =>
+                write!(f, "pc {pc}: `jnz` branches on {condition}, which depends on the inputs"),
+            SymbolicError::ComputedJump { pc, offset } =>
+                write!(f, "pc {pc}: `jnz` jumps by {offset}, which depends on the inputs"),
+            SymbolicError::ComputedToggle { pc, offset } =>
+                write!(f, "pc {pc}: `tgl` targets pc {pc} + ({offset}), which depends on the inputs"),
+            SymbolicError::StepLimit(n) => write!(f, "no result after {n} steps"),
+        }
+    }
+}
+
+impl Error for SymbolicError {}
+
+// The final registers (nonzero ones, in name order) and output as formulas
+// in the inputs. The formulas only hold when every expression in
+// `assumptions` is positive: those are the loop counters that were
+// summarised, which would otherwise count down through zero.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Analysis {
+    pub registers: Vec<(Reg, Expr)>,
+    pub output: Vec<Expr>,
+    pub assumptions: Vec<Expr>,
+}
+
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (r, e) in &self.registers {
+            writeln!(f, "{r} = {e}")?;
+        }
+        for e in &self.output {
+            writeln!(f, "out {e}")?;
+        }
+        for e in &self.assumptions {
+            writeln!(f, "assuming {e} > 0")?;
+        }
+        Ok(())
+    }
+}
+
+const STEP_LIMIT: usize = 1_000_000;
+
+type Env = BTreeMap<Reg, Expr>;
+
+// If `head` is the target of a later `jnz <reg> -k`, the nearest such jump
+// and its counter register.
+fn loop_end(program: &[Instruction], head: usize) -> Option<(usize, Reg)> {
+    program.iter()
+        .enumerate()
+        .skip(head + 1)
+        .find_map(|(pc, i)| match i {
+            Instruction::Jnz(RI::Reg(n), RI::Imm(off)) if (pc as i64).checked_add(*off) == Some(head as i64) => Some((pc, *n)),
+            _ => None,
+        })
+}
+
+// Given what one pass through a loop body does to the registers (`effect`,
+// in terms of their values at the top of the pass), works out what the
+// whole loop does when it runs `counter` times. Each register the body
+// changes must either step by an amount the loop doesn't change, or be set
+// to something the loop doesn't change; the counter itself must step by -1.
+fn close_loop(effect: &Env, counter: Reg, assumptions: &[Expr]) -> Option<Env> {
+    let n = Expr::var(counter);
+    if effect[&counter] != &n - &Expr::constant(1) {
+        return None;
+    }
+    let modified: BTreeSet<Reg> = effect.iter()
+        .filter(|(r, e)| **e != Expr::var(**r))
+        .map(|(r, _)| *r)
+        .collect();
+    let invariant = |e: &Expr| e.vars().is_disjoint(&modified);
+    if !assumptions.iter().all(invariant) {
+        return None;
+    }
+    let mut closed = Env::new();
+    for (r, e) in effect {
+        if *r == counter {
+            closed.insert(*r, Expr::default());
+            continue;
+        }
+        let step = e - &Expr::var(*r);
+        if invariant(&step) {
+            closed.insert(*r, &Expr::var(*r) + &(&n * &step));
+        }
+        else if invariant(e) {
+            closed.insert(*r, e.clone());
+        }
+        else {
+            return None;
+        }
+    }
+    Some(closed)
+}
+
+struct Machine {
+    program: Vec<Instruction>,
+    regs: Env,
+    pc: usize,
+    steps: usize,
+    output: Vec<Expr>,
+    assumptions: Vec<Expr>,
+}
+
+impl Machine {
+    fn value(&self, ri: &RI) -> Expr {
+        match ri {
+            RI::Reg(r) => self.regs[r].clone(),
+            RI::Imm(i) => Expr::constant(*i),
+        }
+    }
+
+    // pc + off, or the end of the program if that's outside it (which
+    // halts, as in VMShell).
+    fn target(&self, off: BigInt) -> usize {
+        let len = self.program.len();
+        usize::try_from(off + self.pc).ok().filter(|t| *t <= len).unwrap_or(len)
+    }
+
+    fn step(&mut self) -> Result<(), SymbolicError> {
+        let pc = self.pc;
+        let mut next = pc + 1;
+        match self.program[pc].original().clone() {
+            Instruction::Cpy(x, y) => {
+                self.regs.insert(y, self.value(&x));
+            },
+            Instruction::Inc(x) => {
+                self.regs.insert(x, &self.regs[&x] + &Expr::constant(1));
+            },
+            Instruction::Dec(x) => {
+                self.regs.insert(x, &self.regs[&x] - &Expr::constant(1));
+            },
+            Instruction::Jnz(x, y) => {
+                let condition = self.value(&x);
+                let Some(c) = condition.as_constant() else {
+                    return Err(SymbolicError::Branch { pc, condition });
+                };
+                if c != BigInt::ZERO {
+                    let offset = self.value(&y);
+                    let Some(off) = offset.as_constant() else {
+                        return Err(SymbolicError::ComputedJump { pc, offset });
+                    };
+                    next = self.target(off);
+                }
+            },
+            Instruction::Tgl(x) => {
+                let offset = self.value(&x);
+                let Some(off) = offset.as_constant() else {
+                    return Err(SymbolicError::ComputedToggle { pc, offset });
+                };
+                let loc = self.target(off);
+                if loc < self.program.len() {
+                    self.program[loc] = self.program[loc].toggled();
+                }
+            },
+            Instruction::Out(x) => {
+                self.output.push(self.regs[&x].clone());
+            },
+            _ => {},
+        }
+        self.pc = next;
+        self.steps += 1;
+        Ok(())
+    }
+
+    // What one pass through the body [head, end) does, starting from
+    // registers holding their own names. Only straight-line code and loops
+    // that can themselves be summarised are allowed.
+    fn body_effect(&self, head: usize, end: usize) -> Option<(Env, Vec<Expr>)> {
+        let mut m = Machine {
+            program: self.program.clone(),
+            regs: self.regs.keys().map(|r| (*r, Expr::var(*r))).collect(),
+            pc: head,
+            steps: 0,
+            output: Vec::new(),
+            assumptions: Vec::new(),
+        };
+        while m.pc < end {
+            if m.pc != head {
+                if let Some((inner, n)) = loop_end(&m.program, m.pc).filter(|(j, _)| *j < end) {
+                    if !m.summarise(inner, n) {
+                        return None;
+                    }
+                    continue;
+                }
+            }
+            match m.program[m.pc] {
+                Instruction::Cpy(..) | Instruction::Inc(_) | Instruction::Dec(_) |
+                Instruction::InvCpy(..) | Instruction::InvInc(_) | Instruction::InvDec(_) => {
+                    m.step().ok()?;
+                },
+                _ => { return None; },
+            }
+        }
+        Some((m.regs, m.assumptions))
+    }
+
+    // Replaces running the loop from pc to the `jnz` at `end` with its
+    // closed form, if it has one. The loop runs as many times as the
+    // counter's current value, so that has to be positive.
+    fn summarise(&mut self, end: usize, counter: Reg) -> bool {
+        let Some((effect, inner)) = self.body_effect(self.pc, end) else {
+            return false;
+        };
+        let Some(closed) = close_loop(&effect, counter, &inner) else {
+            return false;
+        };
+        let n = &self.regs[&counter];
+        match n.as_constant() {
+            Some(c) if c <= BigInt::ZERO => { return false; },
+            Some(_) => {},
+            None => { self.assumptions.push(n.clone()); },
+        }
+        for e in inner {
+            self.assumptions.push(e.subst(&self.regs));
+        }
+        self.regs = closed.iter().map(|(r, e)| (*r, e.subst(&self.regs))).collect();
+        self.pc = end + 1;
+        self.steps += 1;
+        true
+    }
+}
+
+// Runs the program with each register in `inputs` holding its own name,
+// those in `known` holding the given value and the rest 0, and reports the
+// final registers as formulas in the inputs. Loops that count a register
+// down to zero are replaced by their closed form where there is one;
+// anything else has to be decidable from the values so far, so a branch or
+// `tgl` whose target depends on an input stops the analysis.
+pub fn analyse(program: &[Instruction], inputs: &[Reg], known: &[(Reg, i64)]) -> Result<Analysis, SymbolicError> {
+    let mut regs: Env = program.iter()
+        .flat_map(|i| i.registers())
+        .map(|r| (r, Expr::default()))
+        .collect();
+    for r in inputs {
+        regs.insert(*r, Expr::var(*r));
+    }
+    for (r, v) in known {
+        regs.insert(*r, Expr::constant(*v));
+    }
+    let mut m = Machine {
+        program: program.iter().map(|i| i.original().clone()).collect(),
+        regs,
+        pc: 0,
+        steps: 0,
+        output: Vec::new(),
+        assumptions: Vec::new(),
+    };
+    while m.pc < m.program.len() {
+        if m.steps >= STEP_LIMIT {
+            return Err(SymbolicError::StepLimit(STEP_LIMIT));
+        }
+        if let Some((end, n)) = loop_end(&m.program, m.pc) {
+            if m.summarise(end, n) {
+                continue;
+            }
+        }
+        m.step()?;
+    }
+    Ok(Analysis {
+        registers: m.regs.into_iter().filter(|(_, e)| *e != Expr::default()).collect(),
+        output: m.output,
+        assumptions: m.assumptions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembunny::{AssembunnyVM, Dialect, parse_program};
+    use crate::assembunny::test_programs::{DAY12, DAY23};
+
+    #[test]
+    fn symbolic_test() {
+        let a = Reg::Named('a');
+        let program = parse_program(
+            "cpy a b\n\
+             dec b\n\
+             cpy a d\n\
+             cpy 0 a\n\
+             cpy b c\n\
+             inc a\n\
+             dec c\n\
+             jnz c -2\n\
+             dec d\n\
+             jnz d -5\n\
+             cpy 73 c\n\
+             cpy 79 d\n\
+             inc a\n\
+             dec d\n\
+             jnz d -2\n\
+             dec c\n\
+             jnz c -5\n",
+            Dialect::BASIC,
+        ).unwrap();
+        let analysis = analyse(&program, &[a], &[]).unwrap();
+        assert_eq!(analysis.to_string(),
+            "a = a^2 - a + 5767\n\
+             b = a - 1\n\
+             assuming a > 0\n\
+             assuming a - 1 > 0\n");
+        let mut vm = AssembunnyVM::new(&program, Dialect::BASIC);
+        vm.set_reg('a', 9);
+        vm.run();
+        assert_eq!(analysis.registers[0].1.evaluate(&[(a, 9)]), vm.get_reg('a').into());
+
+        let program = parse_program(DAY12, Dialect::BASIC).unwrap();
+        for c in [0, 1] {
+            let analysis = analyse(&program, &[], &[(Reg::Named('c'), c)]).unwrap();
+            let mut vm = AssembunnyVM::new(&program, Dialect::BASIC);
+            vm.optimise();
+            vm.set_reg('c', c);
+            vm.run();
+            assert_eq!(analysis.registers[0], (a, Expr::constant(vm.get_reg('a'))));
+        }
+        assert_eq!(analyse(&program, &[Reg::Named('c')], &[]).unwrap_err().to_string(),
+            "pc 3: `jnz` branches on c, which depends on the inputs");
+
+        // a jump too far to add up runs off the end
+        let program = parse_program("cpy 1 a\njnz a 9223372036854775807\n", Dialect::BASIC).unwrap();
+        assert_eq!(analyse(&program, &[], &[]).unwrap().registers[0], (a, Expr::constant(1)));
+    }
+
+    #[test]
+    fn computed_toggle_test() {
+        let program = parse_program(DAY23, Dialect::TOGGLE).unwrap();
+        let a = Reg::Named('a');
+        assert_eq!(analyse(&program, &[a], &[]).unwrap_err().to_string(),
+            "pc 16: `tgl` targets pc 16 + (2*a - 4), which depends on the inputs");
+        // With `a` known, the toggles can be followed.
+        let analysis = analyse(&program, &[], &[(a, 7)]).unwrap();
+        assert_eq!(analysis.registers[0], (a, Expr::constant(5040 + 73 * 79)));
+    }
+}
@@ -0,0 +1,5 @@
+// The puzzle programs the tests run. The day12, day23 and day25 binaries
+// include the same files in their own tests.
+pub const DAY12: &str = include_str!("../bin/day12.testinput");
+pub const DAY23: &str = include_str!("../bin/day23.testinput");
+pub const DAY25: &str = include_str!("../bin/day25.testinput");
@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs;
 use std::str::FromStr;
 use std::vec::Vec;
-use ya_advent_lib::read::read_input;
+use itertools::Itertools;
+use ya_advent_lib::read::{read_input, ParseErr};
 
 type BotNum = usize;
 type ChipNum = usize;
@@ -12,42 +15,142 @@ enum Dest {
     Bot(BotNum),
 }
 impl Dest {
-    fn new(what: &str, val: &str) -> Self {
-        let val = val.parse::<usize>().unwrap();
+    fn new(what: &str, val: &str) -> Result<Self, ParseErr> {
+        let val = number(val)?;
         match what {
-            "bot" => Self::Bot(val),
-            "output" => Self::Output(val),
-            _ => panic!(),
+            "bot" => Ok(Self::Bot(val)),
+            "output" => Ok(Self::Output(val)),
+            _ => Err(ParseErr::Err(format!("expected `bot` or `output`, found `{what}`"))),
         }
     }
 }
 
+fn number(s: &str) -> Result<usize, ParseErr> {
+    s.parse::<usize>().map_err(|_| ParseErr::Err(format!("`{s}` is not a number")))
+}
+
 enum Input {
     Bot(BotNum,Dest,Dest),
     Value(BotNum,ChipNum),
 }
 
 impl FromStr for Input {
-    type Err = ();
+    type Err = ParseErr;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let w: Vec<&str> = s.split_whitespace().collect();
-        let bot = w[1].parse::<usize>().unwrap();
-        match w[0] {
-            "bot" => {
-                let low = Dest::new(w[5], w[6]);
-                let hi = Dest::new(w[10], w[11]);
-                Ok(Input::Bot(bot,low,hi))
+        match w.as_slice() {
+            ["bot", bot, "gives", "low", "to", lw, lv, "and", "high", "to", hw, hv] => {
+                let low = Dest::new(lw, lv)?;
+                let hi = Dest::new(hw, hv)?;
+                Ok(Input::Bot(number(bot)?,low,hi))
             },
-            "value" => {
-                let bot = w[5].parse::<usize>().unwrap();
-                let dest = w[1].parse::<usize>().unwrap();
-                Ok(Input::Value(bot,dest))
-            }
-            _ => Err(()),
+            ["value", chip, "goes", "to", "bot", bot] => {
+                Ok(Input::Value(number(bot)?,number(chip)?))
+            },
+            _ => Err(ParseErr::Err(
+                "expected \"value <chip> goes to bot <bot>\" or \"bot <bot> gives low to <dest> and high to <dest>\"".to_string()
+            )),
         }
     }
 }
 
+// Problems with the instructions as a whole. Lines are numbered from 1,
+// and `first` is the earlier line a duplicate clashes with.
+#[derive(Debug, Eq, PartialEq)]
+enum InputError {
+    DuplicateRule { bot: BotNum, line: usize, first: usize },
+    // The line is the first to hand the bot a chip.
+    NoRule { bot: BotNum, line: usize },
+    DuplicateChip { chip: ChipNum, line: usize, first: usize },
+    // A rule sending chips to a bot with no rule and no `value` line.
+    UnknownBot { bot: BotNum, line: usize },
+}
+
+impl InputError {
+    fn line(&self) -> usize {
+        match self {
+            InputError::DuplicateRule { line, .. } |
+            InputError::NoRule { line, .. } |
+            InputError::DuplicateChip { line, .. } |
+            InputError::UnknownBot { line, .. } => *line,
+        }
+    }
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::DuplicateRule { bot, line, first } =>
+                write!(f, "line {line}: bot {bot} already has a rule on line {first}"),
+            InputError::NoRule { bot, line } =>
+                write!(f, "line {line}: bot {bot} is given a chip but has no rule"),
+            InputError::DuplicateChip { chip, line, first } =>
+                write!(f, "line {line}: chip {chip} was already placed on line {first}"),
+            InputError::UnknownBot { bot, line } =>
+                write!(f, "line {line}: bot {bot} doesn't exist"),
+        }
+    }
+}
+
+// Everything wrong with the instructions, in line order.
+fn validate(input: &[Input]) -> Result<(), Vec<InputError>> {
+    let mut errors = Vec::new();
+    let mut rules: HashMap<BotNum, usize> = HashMap::new();
+    let mut chips: HashMap<ChipNum, usize> = HashMap::new();
+    let mut has_value: HashSet<BotNum> = HashSet::new();
+    for (idx, i) in input.iter().enumerate() {
+        let line = idx + 1;
+        match i {
+            Input::Bot(bot, _, _) => {
+                if let Some(first) = rules.get(bot) {
+                    errors.push(InputError::DuplicateRule { bot: *bot, line, first: *first });
+                }
+                else {
+                    rules.insert(*bot, line);
+                }
+            },
+            Input::Value(bot, chip) => {
+                has_value.insert(*bot);
+                if let Some(first) = chips.get(chip) {
+                    errors.push(InputError::DuplicateChip { chip: *chip, line, first: *first });
+                }
+                else {
+                    chips.insert(*chip, line);
+                }
+            },
+        }
+    }
+    // Bots without a rule, with the first line handing each a chip.
+    let mut ruleless: BTreeMap<BotNum, usize> = BTreeMap::new();
+    for (idx, i) in input.iter().enumerate() {
+        let targets = match i {
+            Input::Bot(_, lo, hi) => [lo, hi].into_iter()
+                .filter_map(|d| match d {
+                    Dest::Bot(b) => Some(*b),
+                    Dest::Output(_) => None,
+                })
+                .collect(),
+            Input::Value(bot, _) => vec![*bot],
+        };
+        for bot in targets.into_iter().filter(|b| !rules.contains_key(b)) {
+            ruleless.entry(bot).or_insert(idx + 1);
+        }
+    }
+    for (bot, line) in ruleless {
+        if has_value.contains(&bot) {
+            errors.push(InputError::NoRule { bot, line });
+        }
+        else {
+            errors.push(InputError::UnknownBot { bot, line });
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    errors.sort_by_key(|e| e.line());
+    Err(errors)
+}
+
 struct Bot {
     item1: Option<ChipNum>,
     item2: Option<ChipNum>,
@@ -84,9 +187,73 @@ impl Bot {
     }
 }
 
+// Something that happened while stepping, as reported to subscribers and
+// breakpoints. A `ToBot` with no `from` is a `value` line placing its chip,
+// which the first step reports before firing any bots.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Event {
+    Compared { bot: BotNum, low: ChipNum, high: ChipNum },
+    ToBot { from: Option<BotNum>, bot: BotNum, chip: ChipNum },
+    ToOutput { from: BotNum, output: usize, chip: ChipNum },
+}
+
+type Subscriber = Box<dyn FnMut(&Event)>;
+type Predicate = Box<dyn Fn(&Event) -> bool>;
+
+// Breakpoint predicates for the usual questions.
+fn compares(item1: ChipNum, item2: ChipNum) -> impl Fn(&Event) -> bool {
+    let pair = (item1.min(item2), item1.max(item2));
+    move |e| matches!(e, Event::Compared { low, high, .. } if (*low, *high) == pair)
+}
+fn output_receives(output: usize) -> impl Fn(&Event) -> bool {
+    move |e| matches!(e, Event::ToOutput { output: o, .. } if *o == output)
+}
+// A bot is seen holding a chip when it's handed the chip, whether by
+// another bot or by a `value` line.
+fn holds(chip: ChipNum) -> impl Fn(&Event) -> bool {
+    move |e| matches!(e, Event::ToBot { chip: c, .. } if *c == chip)
+}
+
+// "compares 17 61", "output 5" or "holds 61".
+fn parse_breakpoint(s: &str) -> Option<Predicate> {
+    let w: Vec<usize> = s.split_whitespace().skip(1).map(|n| n.parse().ok()).collect::<Option<_>>()?;
+    match (s.split_whitespace().next()?, w.as_slice()) {
+        ("compares", [a, b]) => Some(Box::new(compares(*a, *b))),
+        ("output", [o]) => Some(Box::new(output_receives(*o))),
+        ("holds", [c]) => Some(Box::new(holds(*c))),
+        _ => None,
+    }
+}
+
+// Which full bots `step` fires. Whatever the mode, the order only depends
+// on the input, so outputs and event traces come out the same every run.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum Schedule {
+    // One bot a step: the lowest-numbered full one.
+    Ascending,
+    // One bot a step: the one that has been full longest.
+    Fifo,
+    // Every bot that is full at the start of the step, lowest first.
+    #[default]
+    Rounds,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascending" => Ok(Schedule::Ascending),
+            "fifo" => Ok(Schedule::Fifo),
+            "rounds" => Ok(Schedule::Rounds),
+            _ => Err(format!("unknown schedule `{s}`")),
+        }
+    }
+}
+
 enum StepResult {
     Ok,
-    Break(BotNum),
+    // The first event in the step that a breakpoint matched.
+    Break(Event),
     Deadlock,
 }
 
@@ -94,13 +261,25 @@ struct BotField {
     bots: HashMap<BotNum, Bot>,
     rules: HashMap<BotNum, (Dest, Dest)>,
     outputs: HashMap<usize, Vec<ChipNum>>,
-    breakpoint: Option<(ChipNum, ChipNum)>,
+    subscribers: Vec<Subscriber>,
+    breakpoints: Vec<Predicate>,
+    // The initial `value` lines, as (chip, bot).
+    values: Vec<(ChipNum, BotNum)>,
+    // Every comparison made so far, as (bot, low chip, high chip).
+    compared: Vec<(BotNum, ChipNum, ChipNum)>,
+    schedule: Schedule,
+    // Full bots, in the order they filled up.
+    ready: VecDeque<BotNum>,
+    // Whether the `value` placements have been reported yet.
+    placed: bool,
 }
 impl BotField {
     fn new(input: &[Input]) -> Self {
         let mut bots: HashMap<BotNum, Bot> = HashMap::new();
         let mut rules: HashMap<BotNum, (Dest, Dest)> = HashMap::new();
         let outputs = HashMap::new();
+        let mut values = Vec::new();
+        let mut ready = VecDeque::new();
         for i in input {
             match i {
                 Input::Bot(bot, lowdest, hidest) => {
@@ -109,9 +288,13 @@ impl BotField {
                     rules.insert(*bot, (*lowdest, *hidest));
                 },
                 Input::Value(bot, chip) => {
-                    bots.entry(*bot)
+                    values.push((*chip, *bot));
+                    let b = bots.entry(*bot)
                         .and_modify(|b| {b.take(*chip); })
                         .or_insert(Bot::new(Some(*chip)));
+                    if b.is_full() {
+                        ready.push_back(*bot);
+                    }
                 },
             }
         }
@@ -119,108 +302,597 @@ impl BotField {
             bots,
             rules,
             outputs,
-            breakpoint: None,
+            subscribers: Vec::new(),
+            breakpoints: Vec::new(),
+            values,
+            compared: Vec::new(),
+            schedule: Schedule::default(),
+            ready,
+            placed: false,
         }
     }
-    fn set_breakpoint(&mut self, item1: ChipNum, item2: ChipNum) {
-        self.breakpoint = Some((item1, item2));
+    fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
     }
-    fn step(&mut self) -> StepResult {
-        let queued: Vec<BotNum> = self.bots.iter().filter(|(_,b)| b.is_full()).map(|(k,_)| *k).collect();
-        if queued.is_empty() {
-            return StepResult::Deadlock;
+    // Calls `f` with every event from now on.
+    fn subscribe(&mut self, f: impl FnMut(&Event) + 'static) {
+        self.subscribers.push(Box::new(f));
+    }
+    // Makes `step` return `StepResult::Break` once an event matches.
+    fn add_breakpoint(&mut self, predicate: impl Fn(&Event) -> bool + 'static) {
+        self.breakpoints.push(Box::new(predicate));
+    }
+    // Tells the subscribers, and says whether a breakpoint matched.
+    fn emit(&mut self, event: &Event) -> bool {
+        for f in self.subscribers.iter_mut() {
+            f(event);
         }
-        let mut bp_bot: Option<BotNum> = None;
-        for bot in queued {
-            if let Some((bp_item1, bp_item2)) = self.breakpoint.as_ref() {
-                let bot_item1 = *(self.bots[&bot].item1.as_ref().unwrap());
-                let bot_item2 = *(self.bots[&bot].item2.as_ref().unwrap());
-                if *bp_item1 == bot_item1 && *bp_item2 == bot_item2
-                    || *bp_item1 == bot_item2 && *bp_item2 == bot_item1 {
-                        bp_bot = Some(bot);
+        self.breakpoints.iter().any(|p| p(event))
+    }
+    fn give(&mut self, from: BotNum, dest: Dest, chip: ChipNum) -> Event {
+        match dest {
+            Dest::Bot(d) => {
+                let bot = self.bots.get_mut(&d).unwrap();
+                bot.take(chip);
+                if bot.is_full() {
+                    self.ready.push_back(d);
+                }
+                Event::ToBot { from: Some(from), bot: d, chip }
+            },
+            Dest::Output(o) => {
+                self.outputs.entry(o)
+                    .and_modify(|e| (*e).push(chip))
+                    .or_insert(vec![chip]);
+                Event::ToOutput { from, output: o, chip }
+            },
+        }
+    }
+    fn step(&mut self) -> StepResult {
+        let mut hit: Option<Event> = None;
+        if !self.placed {
+            self.placed = true;
+            for (chip, bot) in self.values.clone() {
+                let e = Event::ToBot { from: None, bot, chip };
+                if self.emit(&e) && hit.is_none() {
+                    hit = Some(e);
                 }
             }
+        }
+        if self.ready.is_empty() {
+            return hit.map_or(StepResult::Deadlock, StepResult::Break);
+        }
+        let queued: Vec<BotNum> = match self.schedule {
+            Schedule::Ascending => {
+                let idx = self.ready.iter().position_min().unwrap();
+                self.ready.remove(idx).into_iter().collect()
+            },
+            Schedule::Fifo => self.ready.pop_front().into_iter().collect(),
+            Schedule::Rounds => self.ready.drain(..).sorted().collect(),
+        };
+        for bot in queued {
             let rule = self.rules[&bot];
             let (lowchip, highchip) = self.bots.get_mut(&bot).unwrap().drop_chips();
-            match rule.0 {
-                Dest::Bot(d) => {
-                    self.bots.get_mut(&d).unwrap().take(lowchip);
-                },
-                Dest::Output(o) => {
-                    self.outputs.entry(o)
-                        .and_modify(|e| (*e).push(lowchip))
-                        .or_insert(vec![lowchip]);
-                },
-            }
-            match rule.1 {
-                Dest::Bot(d) => {
-                    self.bots.get_mut(&d).unwrap().take(highchip);
-                },
-                Dest::Output(o) => {
-                    self.outputs.entry(o)
-                        .and_modify(|e| (*e).push(highchip))
-                        .or_insert(vec![highchip]);
-                },
+            self.compared.push((bot, lowchip, highchip));
+            let events = [
+                Event::Compared { bot, low: lowchip, high: highchip },
+                self.give(bot, rule.0, lowchip),
+                self.give(bot, rule.1, highchip),
+            ];
+            for e in events {
+                if self.emit(&e) && hit.is_none() {
+                    hit = Some(e);
+                }
             }
         }
-        if let Some(bp) = bp_bot {
-            StepResult::Break(bp)
+        if let Some(e) = hit {
+            StepResult::Break(e)
         }
         else {
             StepResult::Ok
         }
     }
+    // Steps until no bot has two chips.
+    fn run(&mut self) {
+        loop {
+            if let StepResult::Deadlock = self.step() {
+                break;
+            }
+        }
+    }
+    // The network as a Graphviz digraph: a source node for each `value`
+    // line, then the bots and outputs joined by their low and high edges.
+    // With `chips`, each edge is also labelled with the chips that have
+    // gone along it so far, so it's most useful after `run`.
+    fn to_dot(&self, chips: bool) -> String {
+        let node = |d: &Dest| match d {
+            Dest::Bot(b) => format!("bot{b}"),
+            Dest::Output(o) => format!("output{o}"),
+        };
+        let mut out = "digraph bots {\n".to_string();
+        for (idx, (chip, bot)) in self.values.iter().enumerate() {
+            out += &format!("    value{idx} [label=\"value {chip}\", shape=plaintext];\n");
+            out += &format!("    value{idx} -> bot{bot};\n");
+        }
+        let mut bots: Vec<&BotNum> = self.bots.keys().collect();
+        bots.sort();
+        for bot in bots {
+            out += &format!("    bot{bot} [label=\"bot {bot}\"];\n");
+        }
+        let mut outputs: Vec<usize> = self.rules.values()
+            .flat_map(|(lo, hi)| [lo, hi])
+            .filter_map(|d| match d {
+                Dest::Output(o) => Some(*o),
+                Dest::Bot(_) => None,
+            })
+            .collect();
+        outputs.sort();
+        outputs.dedup();
+        for o in outputs {
+            out += &format!("    output{o} [label=\"output {o}\", shape=box];\n");
+        }
+        let mut rules: Vec<(&BotNum, &(Dest, Dest))> = self.rules.iter().collect();
+        rules.sort_by_key(|(bot, _)| **bot);
+        for (bot, (lo, hi)) in rules {
+            for (which, dest) in [("low", lo), ("high", hi)] {
+                let mut label = which.to_string();
+                if chips {
+                    let sent: Vec<String> = self.compared.iter()
+                        .filter(|(b, _, _)| b == bot)
+                        .map(|(_, l, h)| if which == "low" { l } else { h }.to_string())
+                        .collect();
+                    if !sent.is_empty() {
+                        label += &format!(": {}", sent.join(", "));
+                    }
+                }
+                out += &format!("    bot{bot} -> {} [label=\"{label}\"];\n", node(dest));
+            }
+        }
+        out += "}\n";
+        out
+    }
+}
+
+// What the static solver found wrong with a set of rules.
+#[derive(Debug, Eq, PartialEq)]
+enum FlowError {
+    // Bots that hand chips round in a loop, each to the next.
+    Cycle(Vec<BotNum>),
+    // A bot that ends up handed these three chips.
+    ThirdChip { bot: BotNum, chips: [ChipNum; 3] },
+    // A bot that gets two chips but has no rule for them.
+    NoRule(BotNum),
+    // No bot ever compares these two chips.
+    NotCompared(ChipNum, ChipNum),
+    // An output that never gets a chip.
+    EmptyOutput(usize),
+}
+
+impl fmt::Display for FlowError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowError::Cycle(bots) => write!(f, "bots {} pass chips round in a cycle",
+                bots.iter().map(|b| b.to_string()).collect::<Vec<_>>().join(" -> ")),
+            FlowError::ThirdChip { bot, chips: [a, b, c] } => write!(f, "bot {bot} is handed a third chip: {a}, {b} and {c}"),
+            FlowError::NoRule(bot) => write!(f, "bot {bot} has two chips but no rule"),
+            FlowError::NotCompared(a, b) => write!(f, "no bot compares chips {a} and {b}"),
+            FlowError::EmptyOutput(o) => write!(f, "output {o} never gets a chip"),
+        }
+    }
+}
+
+// Every comparison and every output's chips, as the rules determine them.
+#[derive(Debug, Default)]
+struct Dataflow {
+    compared: BTreeMap<BotNum, (ChipNum, ChipNum)>,
+    outputs: BTreeMap<usize, Vec<ChipNum>>,
+}
+
+impl Dataflow {
+    fn comparer(&self, item1: ChipNum, item2: ChipNum) -> Result<BotNum, FlowError> {
+        let pair = (item1.min(item2), item1.max(item2));
+        self.compared.iter()
+            .find(|(_, p)| **p == pair)
+            .map(|(b, _)| *b)
+            .ok_or(FlowError::NotCompared(item1, item2))
+    }
+    // The first chip the output got.
+    fn output(&self, output: usize) -> Result<ChipNum, FlowError> {
+        self.outputs.get(&output)
+            .and_then(|chips| chips.first().copied())
+            .ok_or(FlowError::EmptyOutput(output))
+    }
 }
 
-fn process(input: &[Input], item1: ChipNum, item2: ChipNum) -> usize {
+// The first loop found by a depth-first walk of the bot-to-bot edges.
+fn find_cycle(rules: &HashMap<BotNum, (Dest, Dest)>) -> Option<Vec<BotNum>> {
+    fn visit(
+        bot: BotNum,
+        rules: &HashMap<BotNum, (Dest, Dest)>,
+        path: &mut Vec<BotNum>,
+        done: &mut HashSet<BotNum>,
+    ) -> Option<Vec<BotNum>> {
+        if let Some(pos) = path.iter().position(|b| *b == bot) {
+            return Some(path[pos..].to_vec());
+        }
+        if !done.insert(bot) {
+            return None;
+        }
+        path.push(bot);
+        if let Some((lo, hi)) = rules.get(&bot) {
+            for dest in [lo, hi] {
+                if let Dest::Bot(next) = dest {
+                    if let Some(cycle) = visit(*next, rules, path, done) {
+                        return Some(cycle);
+                    }
+                }
+            }
+        }
+        path.pop();
+        None
+    }
+    let mut bots: Vec<&BotNum> = rules.keys().collect();
+    bots.sort();
+    let mut done = HashSet::new();
+    bots.into_iter().find_map(|bot| visit(*bot, rules, &mut Vec::new(), &mut done))
+}
+
+fn receive(
+    held: &mut HashMap<BotNum, Vec<ChipNum>>,
+    ready: &mut VecDeque<BotNum>,
+    bot: BotNum,
+    chip: ChipNum,
+) -> Result<(), FlowError> {
+    let chips = held.entry(bot).or_default();
+    chips.push(chip);
+    match chips.len() {
+        2 => { ready.push_back(bot); },
+        3 => { return Err(FlowError::ThirdChip { bot, chips: [chips[0], chips[1], chips[2]] }); },
+        _ => {},
+    }
+    Ok(())
+}
+
+// Works out the same thing as running a `BotField` to the end, without
+// stepping: with no cycles the rules form a dependency graph, so each bot
+// can be handled once, as soon as its second chip arrives.
+fn dataflow(input: &[Input]) -> Result<Dataflow, FlowError> {
+    let mut rules: HashMap<BotNum, (Dest, Dest)> = HashMap::new();
+    let mut held: HashMap<BotNum, Vec<ChipNum>> = HashMap::new();
+    let mut ready: VecDeque<BotNum> = VecDeque::new();
+    for i in input {
+        match i {
+            Input::Bot(bot, lo, hi) => { rules.insert(*bot, (*lo, *hi)); },
+            Input::Value(bot, chip) => { receive(&mut held, &mut ready, *bot, *chip)?; },
+        }
+    }
+    if let Some(cycle) = find_cycle(&rules) {
+        return Err(FlowError::Cycle(cycle));
+    }
+    let mut flow = Dataflow::default();
+    while let Some(bot) = ready.pop_front() {
+        let chips = &held[&bot];
+        let (low, high) = (chips[0].min(chips[1]), chips[0].max(chips[1]));
+        flow.compared.insert(bot, (low, high));
+        let (lo, hi) = *rules.get(&bot).ok_or(FlowError::NoRule(bot))?;
+        for (dest, chip) in [(lo, low), (hi, high)] {
+            match dest {
+                Dest::Bot(d) => receive(&mut held, &mut ready, d, chip)?,
+                Dest::Output(o) => flow.outputs.entry(o).or_default().push(chip),
+            }
+        }
+    }
+    Ok(flow)
+}
+
+// The rest of these step a `BotField`, which expects rules that `dataflow`
+// accepts: no bot is ever handed a third chip.
+fn process(input: &[Input], schedule: Schedule, item1: ChipNum, item2: ChipNum) -> Result<usize, FlowError> {
     let mut bots = BotField::new(input);
-    bots.set_breakpoint(item1, item2);
+    bots.set_schedule(schedule);
+    bots.add_breakpoint(compares(item1, item2));
     loop {
         match bots.step() {
-            StepResult::Break(bot) => { return bot; },
+            StepResult::Break(Event::Compared { bot, .. }) => { return Ok(bot); },
+            StepResult::Break(_) => unreachable!(),
             StepResult::Ok => {},
-            StepResult::Deadlock => panic!(),
+            StepResult::Deadlock => { return Err(FlowError::NotCompared(item1, item2)); },
         }
     }
 }
 
-fn part1(input: &[Input]) -> usize {
-    process(input, 17, 61)
+fn part1(input: &[Input], schedule: Schedule) -> Result<usize, FlowError> {
+    process(input, schedule, 17, 61)
 }
 
-fn part2(input: &[Input]) -> usize {
+fn part2(input: &[Input], schedule: Schedule) -> Result<usize, FlowError> {
     let mut bots = BotField::new(input);
-    loop {
-        if let StepResult::Deadlock = bots.step() {
-            break;
-        }
-    }
-    bots.outputs[&0][0] *
-    bots.outputs[&1][0] *
-    bots.outputs[&2][0]
+    bots.set_schedule(schedule);
+    bots.run();
+    (0..3)
+        .map(|o| bots.outputs.get(&o).and_then(|chips| chips.first().copied()).ok_or(FlowError::EmptyOutput(o)))
+        .product()
 }
 
 fn main() {
     let input: Vec<Input> = read_input();
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
+    if let Err(errors) = validate(&input) {
+        eprintln!("Invalid input:");
+        for e in errors {
+            eprintln!("  {e}");
+        }
+        std::process::exit(1);
+    }
+    // A bot handed a third chip, or chips going round in a loop, would trip
+    // up the stepping below, so the static solver vets the rules first.
+    let flow = match dataflow(&input) {
+        Ok(flow) => flow,
+        Err(e) => {
+            eprintln!("Invalid rules: {e}");
+            std::process::exit(1);
+        },
+    };
+    // Set DAY10_SCHEDULE to "ascending", "fifo" or "rounds" to pick the
+    // order bots fire in, for both parts and the trace.
+    let schedule = match std::env::var("DAY10_SCHEDULE") {
+        Ok(s) => s.parse().unwrap_or_else(|e| {
+            eprintln!("Bad DAY10_SCHEDULE: {e}, expected ascending, fifo or rounds");
+            std::process::exit(1);
+        }),
+        Err(_) => Schedule::default(),
+    };
+    // Set DAY10_DOT to a file name to write the network there as a
+    // Graphviz graph; set DAY10_DOT_CHIPS as well to label each edge with
+    // the chips a full run sends along it.
+    if let Some(path) = std::env::var_os("DAY10_DOT") {
+        let mut bots = BotField::new(&input);
+        let chips = std::env::var_os("DAY10_DOT_CHIPS").is_some();
+        if chips {
+            bots.run();
+        }
+        if let Err(e) = fs::write(&path, bots.to_dot(chips)) {
+            eprintln!("Can't write DAY10_DOT `{}`: {e}", path.to_string_lossy());
+            std::process::exit(1);
+        }
+    }
+    // Set DAY10_TRACE to print every event of a full run, and DAY10_BREAK
+    // to a breakpoint (see `parse_breakpoint`) to stop it there.
+    if std::env::var_os("DAY10_TRACE").is_some() {
+        let mut bots = BotField::new(&input);
+        bots.set_schedule(schedule);
+        bots.subscribe(|e| println!("{e:?}"));
+        if let Ok(spec) = std::env::var("DAY10_BREAK") {
+            let Some(predicate) = parse_breakpoint(&spec) else {
+                eprintln!("Bad DAY10_BREAK `{spec}`: expected \"compares <chip> <chip>\", \"output <n>\" or \"holds <chip>\"");
+                std::process::exit(1);
+            };
+            bots.add_breakpoint(predicate);
+        }
+        loop {
+            match bots.step() {
+                StepResult::Ok => {},
+                StepResult::Break(e) => { println!("Stopped at {e:?}"); break; },
+                StepResult::Deadlock => { break; },
+            }
+        }
+    }
+    // Set DAY10_DATAFLOW to answer both parts with the static solver
+    // instead of stepping the bots.
+    let answers = if std::env::var_os("DAY10_DATAFLOW").is_some() {
+        [flow.comparer(17, 61), (0..3).map(|o| flow.output(o)).product()]
+    }
+    else {
+        [part1(&input, schedule), part2(&input, schedule)]
+    };
+    for (idx, answer) in answers.iter().enumerate() {
+        match answer {
+            Ok(a) => println!("Part {}: {a}", idx + 1),
+            Err(e) => eprintln!("Part {}: {e}", idx + 1),
+        }
+    }
+    if answers.iter().any(Result::is_err) {
+        std::process::exit(1);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
     use ya_advent_lib::read::test_input;
 
+    const EXAMPLE: &str =
+        "value 5 goes to bot 2\n\
+         bot 2 gives low to bot 1 and high to bot 0\n\
+         value 3 goes to bot 1\n\
+         bot 1 gives low to output 1 and high to bot 0\n\
+         bot 0 gives low to output 2 and high to output 0\n\
+         value 2 goes to bot 2\n";
+
     #[test]
     fn day10_test() {
+        let input: Vec<Input> = test_input(EXAMPLE);
+        for schedule in [Schedule::Ascending, Schedule::Fifo, Schedule::Rounds] {
+            assert_eq!(process(&input, schedule, 2, 5), Ok(2));
+        }
+        assert_eq!(part1(&input, Schedule::default()), Err(FlowError::NotCompared(17, 61)));
+        assert_eq!(part2(&input, Schedule::default()), Ok(30));
+    }
+
+    #[test]
+    fn event_test() {
+        let input: Vec<Input> = test_input(EXAMPLE);
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut bots = BotField::new(&input);
+        let l = log.clone();
+        bots.subscribe(move |e| l.borrow_mut().push(*e));
+        bots.run();
+        assert_eq!(*log.borrow(), vec![
+            Event::ToBot { from: None, bot: 2, chip: 5 },
+            Event::ToBot { from: None, bot: 1, chip: 3 },
+            Event::ToBot { from: None, bot: 2, chip: 2 },
+            Event::Compared { bot: 2, low: 2, high: 5 },
+            Event::ToBot { from: Some(2), bot: 1, chip: 2 },
+            Event::ToBot { from: Some(2), bot: 0, chip: 5 },
+            Event::Compared { bot: 1, low: 2, high: 3 },
+            Event::ToOutput { from: 1, output: 1, chip: 2 },
+            Event::ToBot { from: Some(1), bot: 0, chip: 3 },
+            Event::Compared { bot: 0, low: 3, high: 5 },
+            Event::ToOutput { from: 0, output: 2, chip: 3 },
+            Event::ToOutput { from: 0, output: 0, chip: 5 },
+        ]);
+
+        let mut bots = BotField::new(&input);
+        bots.add_breakpoint(output_receives(0));
+        assert!(matches!(bots.step(), StepResult::Ok));
+        assert!(matches!(bots.step(), StepResult::Ok));
+        assert!(matches!(bots.step(), StepResult::Break(Event::ToOutput { output: 0, chip: 5, .. })));
+        let mut bots = BotField::new(&input);
+        bots.add_breakpoint(parse_breakpoint("holds 3").unwrap());
+        assert!(matches!(bots.step(), StepResult::Break(Event::ToBot { from: None, bot: 1, chip: 3 })));
+        assert!(matches!(bots.step(), StepResult::Break(Event::ToBot { from: Some(1), bot: 0, chip: 3 })));
+        // bot 4 never fills, but its chip from a `value` line is still seen
+        let input: Vec<Input> = test_input(
+            "value 7 goes to bot 4\n\
+             bot 4 gives low to output 0 and high to output 1\n"
+        );
+        let mut bots = BotField::new(&input);
+        bots.add_breakpoint(holds(7));
+        assert!(matches!(bots.step(), StepResult::Break(Event::ToBot { from: None, bot: 4, chip: 7 })));
+        assert!(matches!(bots.step(), StepResult::Deadlock));
+        assert!(parse_breakpoint("compares 17").is_none());
+    }
+
+    #[test]
+    fn dataflow_test() {
+        let input: Vec<Input> = test_input(EXAMPLE);
+        let flow = dataflow(&input).unwrap();
+        assert_eq!(flow.compared, BTreeMap::from([(0, (3, 5)), (1, (2, 3)), (2, (2, 5))]));
+        assert_eq!(flow.outputs, BTreeMap::from([(0, vec![5]), (1, vec![2]), (2, vec![3])]));
+        assert_eq!(flow.comparer(5, 2), Ok(2));
+        assert_eq!(flow.comparer(17, 61), Err(FlowError::NotCompared(17, 61)));
+        assert_eq!(flow.output(2), Ok(3));
+        assert_eq!(flow.output(3).unwrap_err().to_string(), "output 3 never gets a chip");
+        let mut bots = BotField::new(&input);
+        bots.run();
+        assert_eq!(flow.outputs, bots.outputs.into_iter().collect());
+
+        let input: Vec<Input> = test_input(
+            "value 1 goes to bot 0\n\
+             value 2 goes to bot 0\n\
+             bot 0 gives low to bot 1 and high to output 0\n\
+             bot 1 gives low to bot 2 and high to output 1\n\
+             bot 2 gives low to bot 1 and high to output 2\n"
+        );
+        let err = dataflow(&input).unwrap_err();
+        assert_eq!(err, FlowError::Cycle(vec![1, 2]));
+        assert_eq!(err.to_string(), "bots 1 -> 2 pass chips round in a cycle");
+
+        let input: Vec<Input> = test_input(
+            "value 1 goes to bot 0\n\
+             value 2 goes to bot 0\n\
+             value 3 goes to bot 1\n\
+             value 4 goes to bot 1\n\
+             bot 0 gives low to bot 2 and high to bot 2\n\
+             bot 1 gives low to bot 2 and high to output 0\n\
+             bot 2 gives low to output 1 and high to output 2\n"
+        );
+        assert_eq!(dataflow(&input).unwrap_err(), FlowError::ThirdChip { bot: 2, chips: [1, 2, 3] });
+
+        let input: Vec<Input> = test_input(
+            "value 1 goes to bot 0\n\
+             value 2 goes to bot 0\n\
+             bot 0 gives low to bot 1 and high to bot 1\n"
+        );
+        assert_eq!(dataflow(&input).unwrap_err(), FlowError::NoRule(1));
+    }
+
+    #[test]
+    fn validate_test() {
+        let parse = |s: &str| s.parse::<Input>().err().map(|e| e.to_string());
+        assert_eq!(parse("bot 2 gives low to bot 1 and high to bin 0"),
+            Some("expected `bot` or `output`, found `bin`".to_string()));
+        assert_eq!(parse("value x goes to bot 2"), Some("`x` is not a number".to_string()));
+        assert!(parse("bot 2 gives low to bot 1").unwrap().starts_with("expected \"value"));
+
         let input: Vec<Input> = test_input(
             "value 5 goes to bot 2\n\
              bot 2 gives low to bot 1 and high to bot 0\n\
              value 3 goes to bot 1\n\
-             bot 1 gives low to output 1 and high to bot 0\n\
-             bot 0 gives low to output 2 and high to output 0\n\
-             value 2 goes to bot 2\n"
+             bot 1 gives low to output 1 and high to bot 7\n\
+             bot 2 gives low to output 2 and high to output 0\n\
+             value 5 goes to bot 4\n"
         );
-        assert_eq!(process(&input, 2, 5), 2);
+        let errors = validate(&input).unwrap_err();
+        assert_eq!(errors, vec![
+            InputError::UnknownBot { bot: 0, line: 2 },
+            InputError::UnknownBot { bot: 7, line: 4 },
+            InputError::DuplicateRule { bot: 2, line: 5, first: 2 },
+            InputError::DuplicateChip { chip: 5, line: 6, first: 1 },
+            InputError::NoRule { bot: 4, line: 6 },
+        ]);
+        assert_eq!(errors[0].to_string(), "line 2: bot 0 doesn't exist");
+        assert_eq!(errors[4].to_string(), "line 6: bot 4 is given a chip but has no rule");
+    }
+
+    #[test]
+    fn schedule_test() {
+        let input: Vec<Input> = test_input(
+            "value 1 goes to bot 3\n\
+             value 2 goes to bot 3\n\
+             value 3 goes to bot 1\n\
+             value 4 goes to bot 1\n\
+             bot 3 gives low to bot 0 and high to output 0\n\
+             bot 1 gives low to bot 0 and high to output 1\n\
+             bot 0 gives low to output 2 and high to output 3\n"
+        );
+        let run = |schedule: Schedule| {
+            let mut bots = BotField::new(&input);
+            bots.set_schedule(schedule);
+            let mut steps = 0;
+            while let StepResult::Ok = bots.step() {
+                steps += 1;
+            }
+            let order: Vec<BotNum> = bots.compared.iter().map(|(b, _, _)| *b).collect();
+            (steps, order)
+        };
+        assert_eq!(run(Schedule::Ascending), (3, vec![1, 3, 0]));
+        assert_eq!(run(Schedule::Fifo), (3, vec![3, 1, 0]));
+        assert_eq!(run(Schedule::Rounds), (2, vec![1, 3, 0]));
+        assert_eq!("fifo".parse::<Schedule>(), Ok(Schedule::Fifo));
+        assert_eq!("random".parse::<Schedule>(), Err("unknown schedule `random`".to_string()));
+    }
+
+    #[test]
+    fn dot_test() {
+        let input: Vec<Input> = test_input(EXAMPLE);
+        let mut bots = BotField::new(&input);
+        assert_eq!(bots.to_dot(false),
+            "digraph bots {\n\
+             \x20   value0 [label=\"value 5\", shape=plaintext];\n\
+             \x20   value0 -> bot2;\n\
+             \x20   value1 [label=\"value 3\", shape=plaintext];\n\
+             \x20   value1 -> bot1;\n\
+             \x20   value2 [label=\"value 2\", shape=plaintext];\n\
+             \x20   value2 -> bot2;\n\
+             \x20   bot0 [label=\"bot 0\"];\n\
+             \x20   bot1 [label=\"bot 1\"];\n\
+             \x20   bot2 [label=\"bot 2\"];\n\
+             \x20   output0 [label=\"output 0\", shape=box];\n\
+             \x20   output1 [label=\"output 1\", shape=box];\n\
+             \x20   output2 [label=\"output 2\", shape=box];\n\
+             \x20   bot0 -> output2 [label=\"low\"];\n\
+             \x20   bot0 -> output0 [label=\"high\"];\n\
+             \x20   bot1 -> output1 [label=\"low\"];\n\
+             \x20   bot1 -> bot0 [label=\"high\"];\n\
+             \x20   bot2 -> bot1 [label=\"low\"];\n\
+             \x20   bot2 -> bot0 [label=\"high\"];\n\
+             }\n");
+        bots.run();
+        let dot = bots.to_dot(true);
+        assert!(dot.contains("    bot0 -> output2 [label=\"low: 3\"];\n"));
+        assert!(dot.contains("    bot0 -> output0 [label=\"high: 5\"];\n"));
+        assert!(dot.contains("    bot1 -> output1 [label=\"low: 2\"];\n"));
+        assert!(dot.contains("    bot2 -> bot0 [label=\"high: 5\"];\n"));
     }
 }
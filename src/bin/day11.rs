@@ -1,11 +1,12 @@
 use std::cmp::{Ordering, Reverse};
-use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::fmt;
+use std::collections::{BinaryHeap, HashMap};
 use std::str::FromStr;
 use std::vec::Vec;
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use regex::Regex;
-use ya_advent_lib::read::read_input;
+use ya_advent_lib::read::{read_input, ParseErr};
 
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 enum Item {
@@ -13,15 +14,29 @@ enum Item {
     Generator(String),
 }
 impl FromStr for Item {
-    type Err = ();
+    type Err = ParseErr;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s.contains("-compatible") {
-            let chip:String = s.split('-').next().unwrap().to_string();
-            Ok(Item::Chip(chip))
+        lazy_static! {
+            static ref RE: Regex = Regex::new(
+                r"^an? ([a-z]+)(?:(-compatible microchip)| generator)$"
+            ).unwrap();
+        }
+        let caps = RE.captures(s)
+            .ok_or_else(|| ParseErr::Err(format!("expected a generator or microchip, found `{s}`")))?;
+        let name = caps[1].to_string();
+        if caps.get(2).is_some() {
+            Ok(Item::Chip(name))
         }
         else {
-            let gen:String = s.split(' ').next().map(|x| x.to_string()).ok_or(())?;
-            Ok(Item::Generator(gen))
+            Ok(Item::Generator(name))
+        }
+    }
+}
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Item::Chip(n) => write!(f, "{n}-compatible microchip"),
+            Item::Generator(n) => write!(f, "{n} generator"),
         }
     }
 }
@@ -50,225 +65,508 @@ struct Input {
     items: Vec<Item>,
 }
 
+// "first" -> 0, "twenty-third" -> 22, "12th" -> 11, and so on.
+fn parse_ordinal(s: &str) -> Option<usize> {
+    const UNITS: [&str; 19] = [
+        "first", "second", "third", "fourth", "fifth", "sixth", "seventh",
+        "eighth", "ninth", "tenth", "eleventh", "twelfth", "thirteenth",
+        "fourteenth", "fifteenth", "sixteenth", "seventeenth", "eighteenth",
+        "nineteenth",
+    ];
+    const TENS: [(&str, &str); 8] = [
+        ("twenty", "twentieth"), ("thirty", "thirtieth"), ("forty", "fortieth"),
+        ("fifty", "fiftieth"), ("sixty", "sixtieth"), ("seventy", "seventieth"),
+        ("eighty", "eightieth"), ("ninety", "ninetieth"),
+    ];
+    let s = s.to_lowercase();
+    let digits = &s[..s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len())];
+    if !digits.is_empty() {
+        let n = digits.parse::<usize>().ok()?;
+        let suffix = match (n % 100, n % 10) {
+            (11..=13, _) => "th",
+            (_, 1) => "st",
+            (_, 2) => "nd",
+            (_, 3) => "rd",
+            _ => "th",
+        };
+        return (n > 0 && s[digits.len()..] == *suffix).then(|| n - 1);
+    }
+    if let Some(n) = UNITS.iter().position(|u| *u == s) {
+        return Some(n);
+    }
+    TENS.iter().enumerate().find_map(|(idx, (cardinal, ordinal))| {
+        let base = 20 + idx * 10;
+        if s == *ordinal {
+            return Some(base - 1);
+        }
+        let unit = s.strip_prefix(cardinal)?.strip_prefix('-')?;
+        UNITS[..9].iter().position(|u| *u == unit).map(|n| base + n)
+    })
+}
+
+// "The <ordinal> floor contains <items>.", where the items are "nothing
+// relevant" or a list like "a X", "a X and a Y" or "a X, a Y, and a Z".
 impl FromStr for Input {
-    type Err = ();
+    type Err = ParseErr;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         lazy_static! {
-            static ref RE: Regex = Regex::new(
-                r"(?: contains (?:a )?)|(?:,? (?:and )?a )|\."
-            ).unwrap();
+            static ref RE: Regex = Regex::new(r",? and |, ").unwrap();
         }
-        let mut iter = RE.split(s);
-        let floor = (match iter.next() {
-            Some("The first floor") => Some(0),
-            Some("The second floor") => Some(1),
-            Some("The third floor") => Some(2),
-            Some("The fourth floor") => Some(3),
-            _ => None,
-        }).ok_or(())?;
-        let mut items = Vec::new();
-        for i in iter {
-            if i.is_empty() || i.contains("nothing") {
-                continue;
-            }
-            let item = i.parse::<Item>()?;
-            items.push(item);
+        let err = |msg: String| ParseErr::Err(msg);
+        let body = s.strip_suffix('.')
+            .ok_or_else(|| err("expected the line to end with `.`".to_string()))?;
+        let (floor, list) = body.strip_prefix("The ")
+            .and_then(|b| b.split_once(" floor contains "))
+            .ok_or_else(|| err("expected \"The <ordinal> floor contains ...\"".to_string()))?;
+        let floor = parse_ordinal(floor)
+            .ok_or_else(|| err(format!("`{floor}` is not a floor")))?;
+        let items = if list == "nothing relevant" {
+            Vec::new()
         }
+        else {
+            RE.split(list).map(|i| i.parse::<Item>()).collect::<Result<_, _>>()?
+        };
         Ok(Self { floor, items })
     }
 }
 
-#[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
-struct State {
-    step: usize,
-    elevator: usize,
-    floors: Vec<Vec<Item>>,
+// Problems with the input as a whole, which no single line shows.
+#[derive(Debug, Eq, PartialEq)]
+enum InputError {
+    DuplicateFloor(usize),
+    DuplicateItem(Item),
+    UnpairedChip(String),
+    // Nothing describes a floor, so there's no building to solve.
+    NoFloors,
 }
 
-impl State {
-    fn from_input(input: &[Input], additional_items: &Vec<Item>) -> Self {
-        let mut floors: Vec<Vec<Item>> = Vec::with_capacity(4);
-        for _ in 0..4 { floors.push(Vec::new()); }
-        for i in input {
-            floors[i.floor].extend_from_slice(&i.items);
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::DuplicateFloor(n) => write!(f, "floor {} is described more than once", n + 1),
+            InputError::DuplicateItem(i) => write!(f, "the {i} is listed more than once"),
+            InputError::UnpairedChip(n) => write!(f, "the {n}-compatible microchip has no generator"),
+            InputError::NoFloors => write!(f, "there are no floors"),
         }
-        for i in additional_items {
-            floors[0].push(i.clone());
-        }
-        for f in 0..4 {
-            floors.get_mut(f).unwrap().sort();
+    }
+}
+
+// Every chip needs its generator somewhere in the building; a generator
+// without a chip is fine. `additional_items` count as part
+// of the input, so adding one the input already has is an error too.
+fn validate(input: &[Input], additional_items: &[Item]) -> Result<(), InputError> {
+    if let Some(i) = input.iter().map(|i| i.floor).duplicates().next() {
+        return Err(InputError::DuplicateFloor(i));
+    }
+    let items: Vec<&Item> = input.iter().flat_map(|i| &i.items).chain(additional_items).collect();
+    if let Some(i) = items.iter().duplicates().next() {
+        return Err(InputError::DuplicateItem((*i).clone()));
+    }
+    for item in &items {
+        match item {
+            Item::Chip(n) if !items.contains(&&Item::Generator(n.clone())) =>
+                return Err(InputError::UnpairedChip(n.clone())),
+            _ => {},
         }
+    }
+    Ok(())
+}
+
+// The shape of the puzzle: how many floors there are and how many items the
+// elevator can carry at once (it always needs at least one to move).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Building {
+    floors: usize,
+    capacity: usize,
+}
+
+impl Building {
+    // As many floors as the input describes, with the puzzle's two-item
+    // elevator.
+    fn for_input(input: &[Input]) -> Self {
         Self {
-            step: 0,
-            elevator: 0,
-            floors,
+            floors: input.iter().map(|i| i.floor + 1).max().unwrap_or(0),
+            capacity: 2,
         }
     }
 
-    fn valid_floor(items: &Vec<&Item>) -> bool {
-        if items.len() < 2 { return true; }
-        let chips:HashSet<&String> = items.iter()
-            .flat_map(|i| match i {
-                Item::Chip(n) => Some(n),
-                _ => None,
-            })
+    // Where a lone generator's missing chip is kept: one past the top
+    // floor, so it never shares a floor with anything.
+    fn nowhere(&self) -> usize {
+        self.floors
+    }
+
+    // Bits needed to hold one floor number, `nowhere` included, in a packed
+    // state.
+    fn floor_bits(&self) -> u32 {
+        (usize::BITS - self.nowhere().leading_zeros()).max(1)
+    }
+}
+
+// Where everything is: the elevator's floor, and for each element (in the
+// order of `Solution::elements`) the floors its chip and generator are on.
+// An element with no chip has it on `Building::nowhere`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct State {
+    elevator: usize,
+    pairs: Vec<(usize, usize)>,
+}
+
+impl State {
+    // The initial state, along with the element names its pairs stand for.
+    // The input must have passed `validate`.
+    fn from_input(input: &[Input], additional_items: &[Item], building: Building) -> (Vec<String>, Self) {
+        let items: Vec<(usize, &Item)> = input.iter()
+            .flat_map(|i| i.items.iter().map(|item| (i.floor, item)))
+            .chain(additional_items.iter().map(|item| (0, item)))
             .collect();
-        if chips.len() == items.len() { return true; }
-        let gens:HashSet<&String> = items.iter()
-            .flat_map(|i| match i {
-                Item::Generator(n) => Some(n),
-                _ => None,
+        let elements: Vec<String> = items.iter()
+            .map(|(_, i)| match i {
+                Item::Chip(n) | Item::Generator(n) => n.clone(),
             })
+            .sorted()
+            .dedup()
             .collect();
-        chips.iter()
-            .all(|c| gens.contains(c))
+        let floor_of = |item: Item| items.iter()
+            .find(|(_, i)| **i == item)
+            .map(|(f, _)| *f);
+        let pairs = elements.iter()
+            .map(|n| (
+                floor_of(Item::Chip(n.clone())).unwrap_or(building.nowhere()),
+                floor_of(Item::Generator(n.clone())).expect("validate gives every chip a generator"),
+            ))
+            .collect();
+        (elements, Self { elevator: 0, pairs })
+    }
 
+    // Elevator first, then each pair's chip and generator floors, `bits`
+    // bits apiece. A u128 holds 20 pairs on four floors.
+    fn pack(&self, bits: u32) -> u128 {
+        self.pairs.iter()
+            .flat_map(|(c, g)| [c, g])
+            .fold(self.elevator as u128, |acc, f| acc << bits | *f as u128)
     }
 
-    fn valid_next_states(&self) -> Vec<Self> {
-        let adj_floors = match self.elevator {
-            0 => vec![1],
-            3 => vec![2],
-            _ => vec![self.elevator + 1, self.elevator - 1],
-        };
-        self.floors[self.elevator].iter()
-            .map(Some)
-            .chain(std::iter::once(None))
-            .combinations(2)
-            .map(|v| {
-                match (v[0], v[1]) {
-                    (Some(item1), Some(item2)) => {
-                        vec![item1, item2]
-                    },
-                    (Some(item), None) => {
-                        vec![item]
-                    },
-                    _ => panic!(),
-                }
-            })
-            .filter(|items| {
-                let f = self.floors[self.elevator].iter().filter(|i| !items.contains(i)).collect();
-                Self::valid_floor(&f)
-            })
+    fn unpack(key: u128, count: usize, bits: u32) -> Self {
+        let mask = (1 << bits) - 1;
+        let floor = |n: usize| ((key >> (bits as usize * n)) & mask) as usize;
+        Self {
+            elevator: floor(2 * count),
+            pairs: (0..count).rev().map(|n| (floor(2 * n + 1), floor(2 * n))).collect(),
+        }
+    }
+
+    // Elements are interchangeable, so two states that differ only in which
+    // element sits where take as many steps to finish. Packing with the
+    // pairs sorted gives them the same key.
+    fn canonical(&self, bits: u32) -> u128 {
+        let mut pairs = self.pairs.clone();
+        pairs.sort_unstable();
+        Self { elevator: self.elevator, pairs }.pack(bits)
+    }
+
+    // The first pair whose chip shares a floor with another element's
+    // generator without its own generator there to shield it.
+    fn fried_chip(&self) -> Option<usize> {
+        self.pairs.iter()
+            .position(|(c, g)| c != g && self.pairs.iter().any(|(_, g2)| g2 == c))
+    }
+
+    fn is_valid(&self) -> bool {
+        self.fried_chip().is_none()
+    }
+
+    fn valid_next_states(&self, building: Building) -> Vec<Self> {
+        let adj_floors: Vec<usize> = [self.elevator.checked_sub(1), Some(self.elevator + 1)]
+            .into_iter()
+            .flatten()
+            .filter(|f| *f < building.floors)
+            .collect();
+        // (pair, is generator) for every item on the elevator's floor
+        let here: Vec<(usize, bool)> = self.pairs.iter().enumerate()
+            .flat_map(|(idx, (c, g))| [
+                (*c == self.elevator).then_some((idx, false)),
+                (*g == self.elevator).then_some((idx, true)),
+            ])
+            .flatten()
+            .collect();
+        (1..=building.capacity)
+            .flat_map(|n| here.iter().combinations(n))
             .cartesian_product(adj_floors)
-            .filter(|(items, floor)| {
-                let mut f: Vec<&Item> = self.floors[*floor].iter().collect();
-                f.extend_from_slice(items);
-                Self::valid_floor(&f)
+            .map(|(items, floor)| {
+                let mut next = Self { elevator: floor, pairs: self.pairs.clone() };
+                for (idx, is_gen) in items {
+                    let (c, g) = &mut next.pairs[*idx];
+                    *(if *is_gen { g } else { c }) = floor;
+                }
+                next
             })
-            .map(|(items, floor)| self.move_items(items, floor))
+            .filter(|s| s.is_valid())
             .collect()
     }
 
-    fn move_items(&self, items: Vec<&Item>, to_floor: usize) -> Self {
-        let floors: Vec<Vec<Item>> = self.floors.iter().enumerate()
-            .map(|(idx, floor)| {
-                if idx == self.elevator {
-                    floor.iter().filter(|i| !items.contains(i)).cloned().collect()
-                }
-                else if idx == to_floor {
-                    let mut r = floor.clone();
-                    items.iter().for_each(|i| r.push((*i).clone()));
-                    r.sort();
-                    r
-                }
-                else {
-                    floor.clone()
+    // A lower bound on the steps left. Every step carries the elevator
+    // across exactly one of the boundaries between floors, so it's enough
+    // to count the crossings each boundary still needs. With `n` items at
+    // or below a boundary and a `k`-item elevator, each trip up but the
+    // last has to be followed by one down carrying at least one item back,
+    // so getting them all across takes `u` trips up where
+    // u*k - (u-1) >= n. If the elevator is above the boundary it first has
+    // to come down, bringing an item with it. A step only changes the
+    // estimate of the boundary it crosses, and can lower that by at most
+    // one, so the bound is also consistent.
+    fn estimate(&self, building: Building) -> usize {
+        let k = building.capacity;
+        let trips_up = |n: usize| if k == 1 { 1 } else { (n - 1).div_ceil(k - 1).max(1) };
+        (0..building.floors.saturating_sub(1))
+            .map(|boundary| {
+                let n = self.pairs.iter()
+                    .flat_map(|(c, g)| [c, g])
+                    .filter(|f| **f <= boundary)
+                    .count();
+                match n {
+                    0 => 0,
+                    _ if self.elevator <= boundary => 2 * trips_up(n) - 1,
+                    _ => 2 * trips_up(n + 1),
                 }
             })
-            .collect();
-        Self {
-            step: self.step + 1,
-            elevator: to_floor,
-            floors
-        }
+            .sum()
     }
 
-    fn is_complete(&self) -> bool {
-        self.floors[0].is_empty() &&
-        self.floors[1].is_empty() &&
-        self.floors[2].is_empty()
+    fn is_complete(&self, building: Building) -> bool {
+        let top = building.floors - 1;
+        self.pairs.iter().all(|(c, g)| (*c == top || *c == building.nowhere()) && *g == top)
     }
 }
 
-#[derive(Eq, PartialEq, Hash)]
-struct SubState {
-    elevator: usize,
-    floors: Vec<(usize,usize,usize)>, // (pairs, single_chips, single_gens)
+// One elevator trip: the items carried and the floors it went between.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Move {
+    items: Vec<Item>,
+    from: usize,
+    to: usize,
+}
+
+// The states from the initial one to the finished one, and the move that
+// leads from each to the next.
+#[derive(Debug)]
+struct Solution {
+    elements: Vec<String>,
+    path: Vec<State>,
+    moves: Vec<Move>,
+    // How many states the search took off the queue and expanded.
+    expanded: usize,
 }
-impl SubState {
-    fn from_state(state: &State) -> Self {
-        let floors = state.floors.iter()
-            .map(|f| {
-                let chips:HashSet<&String> = f.iter()
-                    .flat_map(|i| match i {
-                        Item::Chip(n) => Some(n),
-                        _ => None,
-                    })
-                    .collect();
-                let gens:HashSet<&String> = f.iter()
-                    .flat_map(|i| match i {
-                        Item::Generator(n) => Some(n),
-                        _ => None,
-                    })
-                    .collect();
-                let pairs = chips.intersection(&gens).count();
-                let single_chips = chips.difference(&gens).count();
-                let single_gens = gens.difference(&chips).count();
-                (pairs, single_chips, single_gens)
+
+impl Solution {
+    fn new(elements: Vec<String>, path: Vec<State>, expanded: usize) -> Self {
+        let moves = path.iter()
+            .tuple_windows()
+            .map(|(a, b)| Move {
+                items: elements.iter().zip(a.pairs.iter().zip(&b.pairs))
+                    .flat_map(|(n, ((c1, g1), (c2, g2)))| [
+                        (c1 != c2).then(|| Item::Chip(n.clone())),
+                        (g1 != g2).then(|| Item::Generator(n.clone())),
+                    ])
+                    .flatten()
+                    .collect(),
+                from: a.elevator,
+                to: b.elevator,
             })
             .collect();
+        Self { elements, path, moves, expanded }
+    }
 
-        Self {
-            elevator: state.elevator,
-            floors,
+    fn steps(&self) -> usize {
+        self.moves.len()
+    }
+
+    // The floor diagram before the first move and after each one, as the
+    // puzzle draws it.
+    fn replay(&self, building: Building) -> String {
+        let width = abbreviation_width(&self.elements);
+        let mut out = self.path[0].draw(&self.elements, width, building);
+        for (n, (m, state)) in self.moves.iter().zip(&self.path[1..]).enumerate() {
+            let carried = m.items.iter().map(|i| abbreviate(i, width)).join(" and ");
+            let dir = if m.to > m.from { "up" } else { "down" };
+            out += &format!("\n{}: {carried} {dir} to F{}\n", n + 1, m.to + 1);
+            out += &state.draw(&self.elements, width, building);
+        }
+        out
+    }
+}
+
+// Shortest element-name prefix that tells every element apart (plutonium
+// and promethium need two letters).
+fn abbreviation_width(names: &[String]) -> usize {
+    (1..)
+        .find(|w| names.iter().map(|n| n.chars().take(*w).collect::<String>()).unique().count() == names.len())
+        .unwrap()
+}
+
+fn abbreviate(item: &Item, width: usize) -> String {
+    let (name, kind) = match item {
+        Item::Chip(n) => (n, 'M'),
+        Item::Generator(n) => (n, 'G'),
+    };
+    let mut chars = name.chars().take(width);
+    chars.next().map(|c| c.to_ascii_uppercase()).into_iter().chain(chars).chain([kind]).collect()
+}
+
+impl State {
+    fn draw(&self, elements: &[String], width: usize, building: Building) -> String {
+        let mut out = String::new();
+        for f in (0..building.floors).rev() {
+            let mut row = format!("F{} {:<3}", f + 1, if self.elevator == f { "E" } else { "." });
+            for (n, (c, g)) in elements.iter().zip(&self.pairs) {
+                for (floor, item) in [(g, Item::Generator(n.clone())), (c, Item::Chip(n.clone()))] {
+                    let cell = if *floor == f { abbreviate(&item, width) } else { ".".to_string() };
+                    row += &format!("{cell:<w$}", w = width + 2);
+                }
+            }
+            out += row.trim_end();
+            out.push('\n');
+        }
+        out
+    }
+}
+
+// How `solve` orders its queue: by steps taken alone, or by steps taken
+// plus `State::estimate`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Search {
+    Bfs,
+    AStar,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+enum SolveError {
+    Invalid(InputError),
+    // The chip is already next to a foreign generator on this floor.
+    Fried { floor: usize, chip: Item },
+    NoSolution,
+    // Gave up after expanding this many states.
+    LimitExceeded(usize),
+    // More items than a packed state has room for.
+    TooManyItems(usize),
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SolveError::Invalid(e) => write!(f, "invalid input: {e}"),
+            SolveError::Fried { floor, chip } => write!(f, "the {chip} on floor {} is fried before the first move", floor + 1),
+            SolveError::NoSolution => write!(f, "no sequence of moves gets everything to the top floor"),
+            SolveError::LimitExceeded(n) => write!(f, "gave up after expanding {n} states"),
+            SolveError::TooManyItems(n) => write!(f, "{n} items are too many to pack into a search state"),
         }
     }
 }
 
-fn solve(input: &[Input], additional_items: Vec<Item>) -> State {
-    let initial = State::from_input(input, &additional_items);
-    let mut visited: HashMap<SubState, usize> = HashMap::new();
-    visited.insert(SubState::from_state(&initial), 0);
+// Plenty for any real input; it only stops a hopeless search from eating
+// all the memory.
+const SEARCH_LIMIT: usize = 10_000_000;
+
+fn solve(
+    input: &[Input],
+    additional_items: Vec<Item>,
+    building: Building,
+    search: Search,
+    limit: usize,
+) -> Result<Solution, SolveError> {
+    validate(input, &additional_items).map_err(SolveError::Invalid)?;
+    if building.floors == 0 {
+        return Err(SolveError::Invalid(InputError::NoFloors));
+    }
+    let (elements, initial) = State::from_input(input, &additional_items, building);
+    if let Some(idx) = initial.fried_chip() {
+        return Err(SolveError::Fried { floor: initial.pairs[idx].0, chip: Item::Chip(elements[idx].clone()) });
+    }
+    let bits = building.floor_bits();
+    let count = elements.len();
+    if bits * (2 * count as u32 + 1) > u128::BITS {
+        return Err(SolveError::TooManyItems(2 * count));
+    }
+    let start = initial.pack(bits);
+    // Keyed by canonical state: the fewest steps it has been reached in, and
+    // the (packed, as-is) state it was reached from.
+    let mut visited: HashMap<u128, (usize, Option<u128>)> = HashMap::new();
+    visited.insert(initial.canonical(bits), (0, None));
+    let priority = |state: &State, step: usize| match search {
+        Search::Bfs => step,
+        Search::AStar => step + state.estimate(building),
+    };
     let mut queue = BinaryHeap::new();
-    queue.push(Reverse(initial));
-    while let Some(state) = queue.pop() {
-        let state = state.0;
-        //println!("{state:?}");
-        if state.is_complete() {
-            return state;
+    queue.push(Reverse((priority(&initial, 0), 0, start)));
+    let mut expanded = 0;
+    while let Some(Reverse((_, step, key))) = queue.pop() {
+        let state = State::unpack(key, count, bits);
+        let canonical = state.canonical(bits);
+        if visited[&canonical].0 < step {
+            // reached more cheaply since this was queued
+            continue;
+        }
+        if expanded == limit {
+            return Err(SolveError::LimitExceeded(expanded));
         }
-        for newstate in state.valid_next_states() {
-            let ss = SubState::from_state(&newstate);
-            if visited.contains_key(&ss) && visited[&ss] <= newstate.step {
+        expanded += 1;
+        if state.is_complete(building) {
+            let mut path = vec![state];
+            while let Some(parent) = visited[&path.last().unwrap().canonical(bits)].1 {
+                path.push(State::unpack(parent, count, bits));
+            }
+            path.reverse();
+            return Ok(Solution::new(elements, path, expanded));
+        }
+        for newstate in state.valid_next_states(building) {
+            let ss = newstate.canonical(bits);
+            if visited.get(&ss).is_some_and(|(s, _)| *s <= step + 1) {
                 continue;
             }
-            visited.insert(ss, newstate.step);
-            queue.push(Reverse(newstate));
+            visited.insert(ss, (step + 1, Some(key)));
+            queue.push(Reverse((priority(&newstate, step + 1), step + 1, newstate.pack(bits))));
         }
     }
-    panic!();
+    Err(SolveError::NoSolution)
 }
 
-fn part1(input: &[Input]) -> usize {
-    let state = solve(input, vec![]);
-    state.step
+fn part1(input: &[Input], search: Search) -> Result<Solution, SolveError> {
+    solve(input, vec![], Building::for_input(input), search, SEARCH_LIMIT)
 }
 
-fn part2(input: &[Input]) -> usize {
-    let state = solve(input, vec![
+fn part2(input: &[Input], search: Search) -> Result<Solution, SolveError> {
+    solve(input, vec![
         Item::Chip("elerium".to_string()),
         Item::Generator("elerium".to_string()),
         Item::Chip("dilithium".to_string()),
         Item::Generator("dilithium".to_string()),
-    ]);
-    state.step
+    ], Building::for_input(input), search, SEARCH_LIMIT)
+}
+
+fn report(part: usize, result: Result<Solution, SolveError>) {
+    match result {
+        Ok(solution) => println!("Part {part}: {} ({} states expanded)", solution.steps(), solution.expanded),
+        Err(e) => println!("Part {part}: {e}"),
+    }
 }
 
 fn main() {
     let input: Vec<Input> = read_input();
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
+    if let Err(e) = validate(&input, &[]) {
+        eprintln!("Invalid input: {e}");
+        std::process::exit(1);
+    }
+    // Set DAY11_BFS to search without the A* estimate, for comparison.
+    let search = if std::env::var_os("DAY11_BFS").is_some() { Search::Bfs } else { Search::AStar };
+    // Set DAY11_MOVES to see the part 1 solution drawn out step by step.
+    if std::env::var_os("DAY11_MOVES").is_some() {
+        let building = Building::for_input(&input);
+        if let Ok(solution) = solve(&input, vec![], building, search, SEARCH_LIMIT) {
+            println!("{}", solution.replay(building));
+        }
+    }
+    report(1, part1(&input, search));
+    report(2, part2(&input, search));
 }
 
 #[cfg(test)]
@@ -276,14 +574,175 @@ mod tests {
     use super::*;
     use ya_advent_lib::read::test_input;
 
+    const EXAMPLE: &str =
+        "The first floor contains a hydrogen-compatible microchip and a lithium-compatible microchip.\n\
+         The second floor contains a hydrogen generator.\n\
+         The third floor contains a lithium generator.\n\
+         The fourth floor contains nothing relevant.\n";
+
     #[test]
     fn day11_test() {
+        let input: Vec<Input> = test_input(EXAMPLE);
+        assert_eq!(part1(&input, Search::AStar).unwrap().steps(), 11);
+        // the extra generators are right next to the unshielded chips
+        let err = part2(&input, Search::AStar).unwrap_err();
+        assert_eq!(err, SolveError::Fried { floor: 0, chip: Item::Chip("hydrogen".to_string()) });
+        assert_eq!(err.to_string(), "the hydrogen-compatible microchip on floor 1 is fried before the first move");
+
+        let building = Building::for_input(&input);
+        let solution = solve(&input, vec![], building, Search::Bfs, SEARCH_LIMIT).unwrap();
+        assert_eq!(solution.moves[0], Move { items: vec![Item::Chip("hydrogen".to_string())], from: 0, to: 1 });
+        let replay = solution.replay(building);
+        assert!(replay.starts_with(
+            "F4 .  .  .  .  .\n\
+             F3 .  .  .  LG .\n\
+             F2 .  HG .  .  .\n\
+             F1 E  .  HM .  LM\n\
+             \n\
+             1: HM up to F2\n\
+             F4 .  .  .  .  .\n\
+             F3 .  .  .  LG .\n\
+             F2 E  HG HM .  .\n\
+             F1 .  .  .  .  LM\n"));
+        assert!(replay.ends_with(
+            "11: HM and LM up to F4\n\
+             F4 E  HG HM LG LM\n\
+             F3 .  .  .  .  .\n\
+             F2 .  .  .  .  .\n\
+             F1 .  .  .  .  .\n"));
+    }
+
+    #[test]
+    fn part2_test() {
+        let input: Vec<Input> = test_input(include_str!("day11.testinput"));
+        assert_eq!(part1(&input, Search::AStar).unwrap().steps(), 31);
+        assert_eq!(part2(&input, Search::AStar).unwrap().steps(), 55);
+    }
+
+    #[test]
+    fn astar_test() {
+        let input: Vec<Input> = test_input(include_str!("day11.testinput"));
+        let bfs = part1(&input, Search::Bfs).unwrap();
+        let astar = part1(&input, Search::AStar).unwrap();
+        assert_eq!(bfs.steps(), astar.steps());
+        assert!(astar.expanded < bfs.expanded);
+        // never more than the steps actually left
+        let building = Building::for_input(&input);
+        for (n, state) in bfs.path.iter().enumerate() {
+            assert!(state.estimate(building) <= 31 - n);
+        }
+    }
+
+    #[test]
+    fn pack_test() {
+        let state = State { elevator: 2, pairs: vec![(3, 1), (0, 0), (1, 2)] };
+        assert_eq!(State::unpack(state.pack(2), 3, 2), state);
+        let swapped = State { elevator: 2, pairs: vec![(1, 2), (3, 1), (0, 0)] };
+        assert_ne!(swapped.pack(2), state.pack(2));
+        assert_eq!(swapped.canonical(2), state.canonical(2));
+        assert_eq!(Building { floors: 3, capacity: 2 }.floor_bits(), 2);
+        assert_eq!(Building { floors: 4, capacity: 2 }.floor_bits(), 3);
+        assert_eq!(Building { floors: 8, capacity: 2 }.floor_bits(), 4);
+    }
+
+    #[test]
+    fn parse_test() {
+        let parse = |s: &str| s.parse::<Input>().map_err(|e| e.to_string());
+        let input = parse("The second floor contains a hydrogen generator, a lithium generator, and a lithium-compatible microchip.").unwrap();
+        assert_eq!(input.floor, 1);
+        assert_eq!(input.items, vec![
+            Item::Generator("hydrogen".to_string()),
+            Item::Generator("lithium".to_string()),
+            Item::Chip("lithium".to_string()),
+        ]);
+        assert!(parse("The fourth floor contains nothing relevant.").unwrap().items.is_empty());
+        assert_eq!(parse("The fourth floor contains nothing relevant").err().unwrap(),
+            "expected the line to end with `.`");
+        assert_eq!(parse("The top floor contains nothing relevant.").err().unwrap(),
+            "`top` is not a floor");
+        assert_eq!(parse("The first floor has a hydrogen generator.").err().unwrap(),
+            "expected \"The <ordinal> floor contains ...\"");
+        assert_eq!(parse("The first floor contains a hydrogen reactor.").err().unwrap(),
+            "expected a generator or microchip, found `a hydrogen reactor`");
+        assert_eq!(parse("The first floor contains a hydrogen generator and lithium-compatible microchip.").err().unwrap(),
+            "expected a generator or microchip, found `lithium-compatible microchip`");
+    }
+
+    #[test]
+    fn validate_test() {
+        let input: Vec<Input> = test_input(EXAMPLE);
+        assert_eq!(validate(&input, &[]), Ok(()));
+        let input: Vec<Input> = test_input(
+            "The first floor contains a hydrogen-compatible microchip and a lithium-compatible microchip.\n\
+             The second floor contains a hydrogen generator.\n\
+             The second floor contains a lithium generator.\n"
+        );
+        assert_eq!(validate(&input, &[]), Err(InputError::DuplicateFloor(1)));
+        let input: Vec<Input> = test_input(
+            "The first floor contains a hydrogen-compatible microchip and a lithium-compatible microchip.\n\
+             The second floor contains a hydrogen generator.\n"
+        );
+        assert_eq!(validate(&input, &[]), Err(InputError::UnpairedChip("lithium".to_string())));
+        assert_eq!(validate(&input, &[]).unwrap_err().to_string(), "the lithium-compatible microchip has no generator");
+        assert_eq!(part1(&input, Search::AStar).unwrap_err(),
+            SolveError::Invalid(InputError::UnpairedChip("lithium".to_string())));
+        let input: Vec<Input> = test_input(
+            "The first floor contains an elerium-compatible microchip and an elerium generator.\n\
+             The second floor contains nothing relevant.\n"
+        );
+        assert!(part1(&input, Search::AStar).is_ok());
+        assert_eq!(part2(&input, Search::AStar).unwrap_err(),
+            SolveError::Invalid(InputError::DuplicateItem(Item::Chip("elerium".to_string()))));
+        let input: Vec<Input> = test_input(
+            "The first floor contains a hydrogen-compatible microchip and a hydrogen generator.\n\
+             The second floor contains a hydrogen generator.\n"
+        );
+        assert_eq!(validate(&input, &[]), Err(InputError::DuplicateItem(Item::Generator("hydrogen".to_string()))));
+        assert_eq!(part2(&[], Search::AStar).unwrap_err(), SolveError::Invalid(InputError::NoFloors));
+        // a generator doesn't need a chip, but still fries other chips
+        let input: Vec<Input> = test_input(
+            "The first floor contains a hydrogen generator, a hydrogen-compatible microchip, and a lithium generator.\n\
+             The second floor contains nothing relevant.\n"
+        );
+        assert_eq!(validate(&input, &[]), Ok(()));
+        let solution = part1(&input, Search::AStar).unwrap();
+        assert_eq!(solution.steps(), 3);
+        assert_eq!(solution.moves[1].items, vec![Item::Generator("hydrogen".to_string())]);
+    }
+
+    #[test]
+    fn building_test() {
+        assert_eq!(parse_ordinal("fifth"), Some(4));
+        assert_eq!(parse_ordinal("twentieth"), Some(19));
+        assert_eq!(parse_ordinal("thirty-second"), Some(31));
+        assert_eq!(parse_ordinal("12th"), Some(11));
+        assert_eq!(parse_ordinal("22nd"), Some(21));
+        assert_eq!(parse_ordinal("12nd"), None);
+        assert_eq!(parse_ordinal("zeroth"), None);
+
         let input: Vec<Input> = test_input(
             "The first floor contains a hydrogen-compatible microchip and a lithium-compatible microchip.\n\
              The second floor contains a hydrogen generator.\n\
-             The third floor contains a lithium generator.\n\
-             The fourth floor contains nothing relevant.\n"
+             The fifth floor contains a lithium generator.\n"
         );
-        assert_eq!(part1(&input), 11);
+        let building = Building::for_input(&input);
+        assert_eq!(building, Building { floors: 5, capacity: 2 });
+        assert_eq!(solve(&input, vec![], building, Search::AStar, SEARCH_LIMIT).unwrap().steps(), 12);
+        let input: Vec<Input> = test_input(EXAMPLE);
+        assert_eq!(solve(&input, vec![], Building { floors: 4, capacity: 3 }, Search::AStar, SEARCH_LIMIT).unwrap().steps(), 9);
+        assert_eq!(solve(&input, vec![], Building { floors: 4, capacity: 4 }, Search::AStar, SEARCH_LIMIT).unwrap().steps(), 7);
+        // one item at a time, every trip back down undoes a trip up
+        assert_eq!(solve(&input, vec![], Building { floors: 4, capacity: 1 }, Search::AStar, SEARCH_LIMIT).unwrap_err(),
+            SolveError::NoSolution);
+        assert_eq!(solve(&input, vec![], Building { floors: 4, capacity: 2 }, Search::Bfs, 5).unwrap_err(),
+            SolveError::LimitExceeded(5));
+        // 16 pairs on four floors need more than a u64, 32 more than a u128
+        let elements = |n: usize| (0..n)
+            .flat_map(|e| [Item::Chip(format!("e{e}")), Item::Generator(format!("e{e}"))])
+            .collect::<Vec<_>>();
+        assert_eq!(solve(&[], elements(16), Building { floors: 4, capacity: 2 }, Search::AStar, 5).unwrap_err(),
+            SolveError::LimitExceeded(5));
+        assert_eq!(solve(&[], elements(32), Building { floors: 4, capacity: 2 }, Search::AStar, 5).unwrap_err(),
+            SolveError::TooManyItems(64));
     }
 }
@@ -62,7 +62,7 @@ impl Ring {
         (*t).gifts = 0;
         */
         if target == self.opposing_elf_idx {
-            self.opposing_elf_idx = if self.remaining % 2 == 0 {
+            self.opposing_elf_idx = if self.remaining.is_multiple_of(2) {
                 prev_idx
             } else {
                 next_idx
@@ -1,208 +1,71 @@
-use std::str::FromStr;
-use std::vec::Vec;
-use ya_advent_lib::read::read_input;
-use ya_advent_lib::vm_shell::{CPU, VM, VMShell, InstructionResult};
-use ya_advent_lib::vm_display::{InstructionDisplay, Formatter};
-use ya_advent_lib::vm_debugger::Debugger;
+use advent2016::assembunny::{Action, Arith, AssembunnyVM, Dialect, Instruction, Reg, RunConfig, Stop, load};
 
-#[derive(Clone, Copy, Debug)]
-enum RI {
-    Reg(char),
-    Imm(i64),
-}
-
-impl FromStr for RI {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let f = s.chars().next().unwrap();
-        match f {
-            'a' ..= 'z' => Ok(RI::Reg(f)),
-            _ => Ok(RI::Imm(s.parse::<i64>().unwrap())),
-        }
+// The answer in `a`, or how the run stopped if it didn't halt (already
+// reported on stderr by `run_configured`).
+fn part1(input: &[Instruction], config: &RunConfig) -> Result<i64, Stop> {
+    let mut vm = AssembunnyVM::new(input, Dialect::TOGGLE);
+    vm.optimise();
+    vm.set_reg('a', 7);
+    match vm.run_configured(config) {
+        Stop::Halted => Ok(vm.get_reg('a')),
+        stop => Err(stop),
     }
 }
-impl InstructionDisplay<i64> for RI {
-    fn fmt(&self, fmt: &mut Formatter<i64>) {
-        match self {
-            RI::Reg(c) => fmt.add_register(c.to_string()),
-            RI::Imm(i) => fmt.add_integer(*i),
-        };
-    }
-}
-
-#[derive(Debug, Clone)]
-enum Instruction {
-    Cpy(RI,char),
-    InvCpy(RI,RI),
-    Inc(char),
-    InvInc(RI),
-    Dec(char),
-    InvDec(RI),
-    Jnz(RI,RI),
-    Tgl(RI),
-}
 
-impl FromStr for Instruction {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut iter = s.split(' ');
-        let opcode = iter.next().ok_or(())?;
-        let x = iter.next().ok_or(())?;
-        let a = x.parse::<RI>()?;
-        let x = x.chars().next().ok_or(())?;
-        let y = iter.next();
-        let b = y.map(|v| v.parse::<RI>().unwrap());
-        let y = y.map(|v| v.chars().next().unwrap());
-        match opcode {
-            "cpy" => Ok(Instruction::Cpy(a, y.unwrap())),
-            "inc" => Ok(Instruction::Inc(x)),
-            "dec" => Ok(Instruction::Dec(x)),
-            "jnz" => Ok(Instruction::Jnz(a, b.unwrap())),
-            "tgl" => Ok(Instruction::Tgl(a)),
-            _ => Err(()),
-        }
-    }
-}
-impl InstructionDisplay<i64> for Instruction {
-    fn fmt(&self, fmt: &mut Formatter<i64>) {
-        match self {
-            Instruction::Cpy(x,y) => {
-                fmt.add_opcode("cpy".into());
-                x.fmt(fmt);
-                fmt.add_register(y.to_string());
-            },
-            Instruction::InvCpy(x,y) => {
-                fmt.add_opcode("*cpy".into());
-                x.fmt(fmt);
-                y.fmt(fmt);
-            },
-            Instruction::Inc(x) => {
-                fmt.add_opcode("inc".into());
-                fmt.add_register(x.to_string());
-            },
-            Instruction::InvInc(x) => {
-                fmt.add_opcode("inc".into());
-                x.fmt(fmt);
-            },
-            Instruction::Dec(x) => {
-                fmt.add_opcode("dec".into());
-                fmt.add_register(x.to_string());
-            },
-            Instruction::InvDec(x) => {
-                fmt.add_opcode("dec".into());
-                x.fmt(fmt);
-            },
-            Instruction::Jnz(x,y) => {
-                fmt.add_opcode("jnz".into());
-                x.fmt(fmt);
-                y.fmt(fmt);
-            },
-            Instruction::Tgl(x) => {
-                fmt.add_opcode("tgl".into());
-                x.fmt(fmt);
-            },
-        };
+fn part2(input: &[Instruction], config: &RunConfig) -> Result<i64, Stop> {
+    let mut vm = AssembunnyVM::new(input, Dialect::TOGGLE);
+    vm.optimise();
+    vm.set_reg('a', 12);
+    match vm.run_configured(config) {
+        Stop::Halted => Ok(vm.get_reg('a')),
+        stop => Err(stop),
     }
 }
 
-struct AssembunnyCPU { }
-
-impl CPU<char, i64, Instruction> for AssembunnyCPU {
-    fn execute_instruction(&self, vm: &mut VM<char, i64, Instruction>, i: &Instruction) -> InstructionResult {
-        let resolve = |ri| {
-            match ri {
-                RI::Imm(x) => x,
-                RI::Reg(r) => vm.get_reg(r),
-            }
-        };
-
-        match i {
-            Instruction::Cpy(x, y) => {
-                vm.set_reg(*y, resolve(*x));
-            },
-            Instruction::Inc(x) => {
-                let n = vm.get_reg(*x) + 1;
-                vm.set_reg(*x, n);
-            },
-            Instruction::Dec(x) => {
-                let n = vm.get_reg(*x) - 1;
-                vm.set_reg(*x, n);
-            },
-            Instruction::Jnz(x, y) => {
-                if resolve(*x) != 0 {
-                    let jump = resolve(*y);
-                    if jump < 0 {
-                        return InstructionResult::JumpBck(jump.unsigned_abs() as usize);
-                    }
-                    else {
-                        return InstructionResult::JumpFwd(jump as usize);
-                    }
-                }
-            },
-            Instruction::Tgl(x) => {
-                let loc = vm.pc as i64 + resolve(*x);
-                if loc >= 0 && loc < vm.program.len() as i64 {
-                    let ninst = match vm.program[loc as usize] {
-                        Instruction::Inc(x) => Instruction::Dec(x),
-                        Instruction::InvInc(x) => Instruction::InvDec(x),
-                        Instruction::Dec(x) => Instruction::Inc(x),
-                        Instruction::InvDec(x) => Instruction::InvInc(x),
-                        Instruction::Tgl(RI::Reg(x)) => Instruction::Inc(x),
-                        Instruction::Tgl(RI::Imm(x)) => Instruction::InvInc(RI::Imm(x)),
-                        Instruction::Jnz(x, RI::Reg(y)) => Instruction::Cpy(x, y),
-                        Instruction::Jnz(x, RI::Imm(y)) => Instruction::InvCpy(x, RI::Imm(y)),
-                        Instruction::Cpy(x, y) => Instruction::Jnz(x, RI::Reg(y)),
-                        Instruction::InvCpy(x, y) => Instruction::Jnz(x, y),
-                    };
-                    vm.program[loc as usize] = ninst;
-                }
+fn main() {
+    let (input, config) = match load(Dialect::TOGGLE) {
+        Ok(Action::Run(input, config)) => (input, config),
+        Ok(Action::Print(text)) => {
+            print!("{text}");
+            return;
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        },
+    };
+    if config.arith == Arith::Big {
+        let mut failed = false;
+        let parts: [&[(Reg, i64)]; 2] = [&[(Reg::Named('a'), 7)], &[(Reg::Named('a'), 12)]];
+        for (idx, inputs) in parts.into_iter().enumerate() {
+            match config.run_big(&input, Dialect::TOGGLE, inputs) {
+                Ok(vm) => println!("Part {}: {}", idx + 1, vm.get_reg('a')),
+                Err(e) => {
+                    eprintln!("Part {}: {e}", idx + 1);
+                    failed = true;
+                },
             }
-            _ => {},
         }
-        InstructionResult::Ok
+        if failed {
+            std::process::exit(1);
+        }
+        return;
     }
-}
-
-struct AssembunnyVM {
-    cpu: AssembunnyCPU,
-    shell: VMShell<char, i64, Instruction>,
-}
-
-impl AssembunnyVM {
-    fn new(program: &[Instruction], a: i64) -> Self {
-        let cpu = AssembunnyCPU{};
-        let mut shell = VMShell::new(program.to_owned(), 0);
-        shell.vm.set_reg('a', a);
-        Self { cpu, shell }
+    let answers = [part1(&input, &config), part2(&input, &config)];
+    for (idx, answer) in answers.iter().enumerate() {
+        if let Ok(a) = answer {
+            println!("Part {}: {a}", idx + 1);
+        }
     }
-    fn run(&mut self) {
-        self.shell.run(&self.cpu);
+    if answers.iter().any(Result::is_err) {
+        std::process::exit(1);
     }
 }
 
-fn part1(input: &[Instruction]) -> i64 {
-    let mut vm = AssembunnyVM::new(input, 7);
-    vm.run();
-    //let _ = Debugger::run(&mut vm.shell, &vm.cpu);
-    vm.shell.vm.get_reg('a')
-}
-
-fn part2(input: &[Instruction]) -> i64 {
-    let mut vm = AssembunnyVM::new(input, 12);
-    //vm.run();
-    let _ = Debugger::run(&mut vm.shell, &vm.cpu);
-    vm.shell.vm.get_reg('a')
-}
-
-fn main() {
-    let input: Vec<Instruction> = read_input();
-    println!("Part 1: {}", part1(&input));
-    println!("Part 2: {}", part2(&input));
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use advent2016::assembunny::Trap;
     use ya_advent_lib::read::test_input;
 
     #[test]
@@ -216,6 +79,14 @@ mod tests {
              dec a\n\
              dec a\n"
         );
-        assert_eq!(part1(&input), 3);
+        let config = RunConfig::default();
+        assert_eq!(part1(&input, &config), Ok(3));
+
+        let input: Vec<Instruction> = test_input(include_str!("day23.testinput"));
+        assert_eq!(part2(&input, &config), Ok(479001600 + 73 * 79));
+        // 25! doesn't fit in an i64, so there's no answer to give
+        let mut config = RunConfig::default();
+        config.apply_command("set a=25").unwrap();
+        assert_eq!(part2(&input, &config), Err(Stop::Trapped(Trap::Overflow)));
     }
 }
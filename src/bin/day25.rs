@@ -1,204 +1,48 @@
-use std::str::FromStr;
-use std::vec::Vec;
-use ya_advent_lib::read::read_input;
-use ya_advent_lib::vm_shell::{CPU, VM, VMShell, InstructionResult};
-use ya_advent_lib::vm_display::{InstructionDisplay, Formatter};
-use ya_advent_lib::vm_debugger::Debugger;
+use advent2016::assembunny::{Action, Arith, AssembunnyVM, Dialect, Instruction, find_clock_signal, load};
 
-#[derive(Clone, Copy, Debug)]
-enum RI {
-    Reg(char),
-    Imm(i64),
-}
-
-impl FromStr for RI {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let f = s.chars().next().unwrap();
-        match f {
-            'a' ..= 'z' => Ok(RI::Reg(f)),
-            _ => Ok(RI::Imm(s.parse::<i64>().unwrap())),
-        }
-    }
-}
-impl InstructionDisplay<i64> for RI {
-    fn fmt(&self, fmt: &mut Formatter<i64>) {
-        match self {
-            RI::Reg(c) => fmt.add_register(c.to_string()),
-            RI::Imm(i) => fmt.add_integer(*i),
-        };
-    }
-}
-
-#[derive(Debug, Clone)]
-enum Instruction {
-    Cpy(RI,char),
-    InvCpy(RI,RI),
-    Inc(char),
-    InvInc(RI),
-    Dec(char),
-    InvDec(RI),
-    Jnz(RI,RI),
-    Tgl(RI),
-    Out(char),
+fn part1(input: &[Instruction]) -> i64 {
+    find_clock_signal(input, 1..i64::MAX).unwrap()
 }
 
-impl FromStr for Instruction {
-    type Err = ();
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut iter = s.split(' ');
-        let opcode = iter.next().ok_or(())?;
-        let x = iter.next().ok_or(())?;
-        let a = x.parse::<RI>()?;
-        let x = x.chars().next().ok_or(())?;
-        let y = iter.next();
-        let b = y.map(|v| v.parse::<RI>().unwrap());
-        let y = y.map(|v| v.chars().next().unwrap());
-        match opcode {
-            "cpy" => Ok(Instruction::Cpy(a, y.unwrap())),
-            "inc" => Ok(Instruction::Inc(x)),
-            "dec" => Ok(Instruction::Dec(x)),
-            "jnz" => Ok(Instruction::Jnz(a, b.unwrap())),
-            "tgl" => Ok(Instruction::Tgl(a)),
-            "out" => Ok(Instruction::Out(x)),
-            _ => Err(()),
-        }
+fn main() {
+    let (input, config) = match load(Dialect::FULL) {
+        Ok(Action::Run(input, config)) => (input, config),
+        Ok(Action::Print(text)) => {
+            print!("{text}");
+            return;
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        },
+    };
+    if config.arith == Arith::Big {
+        eprintln!("--arith big is not supported: the clock search runs on i64 registers");
+        std::process::exit(1);
     }
-}
-impl InstructionDisplay<i64> for Instruction {
-    fn fmt(&self, fmt: &mut Formatter<i64>) {
-        match self {
-            Instruction::Cpy(x,y) => {
-                fmt.add_opcode("cpy".into());
-                x.fmt(fmt);
-                fmt.add_register(y.to_string());
-            },
-            Instruction::InvCpy(x,y) => {
-                fmt.add_opcode("*cpy".into());
-                x.fmt(fmt);
-                y.fmt(fmt);
-            },
-            Instruction::Inc(x) => {
-                fmt.add_opcode("inc".into());
-                fmt.add_register(x.to_string());
-            },
-            Instruction::InvInc(x) => {
-                fmt.add_opcode("inc".into());
-                x.fmt(fmt);
-            },
-            Instruction::Dec(x) => {
-                fmt.add_opcode("dec".into());
-                fmt.add_register(x.to_string());
-            },
-            Instruction::InvDec(x) => {
-                fmt.add_opcode("dec".into());
-                x.fmt(fmt);
-            },
-            Instruction::Jnz(x,y) => {
-                fmt.add_opcode("jnz".into());
-                x.fmt(fmt);
-                y.fmt(fmt);
-            },
-            Instruction::Tgl(x) => {
-                fmt.add_opcode("tgl".into());
-                x.fmt(fmt);
-            },
-            Instruction::Out(x) => {
-                fmt.add_opcode("out".into());
-                fmt.add_register(x.to_string());
-            },
-        };
+    // The clock program never halts, so a headless run needs something to
+    // stop it.
+    if !config.debug && !config.has_stop_condition() && (config.profile || !config.registers.is_empty()) {
+        eprintln!("the clock program never halts: --profile and --set need --break, --watch or --max-steps");
+        std::process::exit(1);
     }
-}
-
-struct AssembunnyCPU { }
-
-impl CPU<char, i64, Instruction> for AssembunnyCPU {
-    fn execute_instruction(&self, vm: &mut VM<char, i64, Instruction>, i: &Instruction) -> InstructionResult {
-        let resolve = |ri| {
-            match ri {
-                RI::Imm(x) => x,
-                RI::Reg(r) => vm.get_reg(r),
-            }
-        };
-
-        match i {
-            Instruction::Cpy(x, y) => {
-                vm.set_reg(*y, resolve(*x));
-            },
-            Instruction::Inc(x) => {
-                let n = vm.get_reg(*x) + 1;
-                vm.set_reg(*x, n);
-            },
-            Instruction::Dec(x) => {
-                let n = vm.get_reg(*x) - 1;
-                vm.set_reg(*x, n);
-            },
-            Instruction::Jnz(x, y) => {
-                if resolve(*x) != 0 {
-                    let jump = resolve(*y);
-                    if jump < 0 {
-                        return InstructionResult::JumpBck(jump.unsigned_abs() as usize);
-                    }
-                    else {
-                        return InstructionResult::JumpFwd(jump as usize);
-                    }
-                }
-            },
-            Instruction::Tgl(x) => {
-                let loc = vm.pc as i64 + resolve(*x);
-                if loc >= 0 && loc < vm.program.len() as i64 {
-                    let ninst = match vm.program[loc as usize] {
-                        Instruction::Inc(x) => Instruction::Dec(x),
-                        Instruction::InvInc(x) => Instruction::InvDec(x),
-                        Instruction::Dec(x) => Instruction::Inc(x),
-                        Instruction::InvDec(x) => Instruction::InvInc(x),
-                        Instruction::Tgl(RI::Reg(x)) => Instruction::Inc(x),
-                        Instruction::Tgl(RI::Imm(x)) => Instruction::InvInc(RI::Imm(x)),
-                        Instruction::Jnz(x, RI::Reg(y)) => Instruction::Cpy(x, y),
-                        Instruction::Jnz(x, RI::Imm(y)) => Instruction::InvCpy(x, RI::Imm(y)),
-                        Instruction::Cpy(x, y) => Instruction::Jnz(x, RI::Reg(y)),
-                        Instruction::InvCpy(x, y) => Instruction::Jnz(x, y),
-                        Instruction::Out(x) => Instruction::Out(x),
-                    };
-                    vm.program[loc as usize] = ninst;
-                }
-            },
-            Instruction::Out(_) => {
-                return InstructionResult::Break;
-            },
-            _ => {},
-        }
-        InstructionResult::Ok
+    let a = part1(&input);
+    println!("Part 1: {a}");
+    if config.debug || config.has_stop_condition() {
+        let mut vm = AssembunnyVM::new(&input, Dialect::FULL);
+        vm.set_reg('a', a);
+        vm.run_configured(&config);
     }
 }
 
-struct AssembunnyVM {
-    cpu: AssembunnyCPU,
-    shell: VMShell<char, i64, Instruction>,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ya_advent_lib::read::test_input;
 
-impl AssembunnyVM {
-    fn new(program: &[Instruction], a: i64) -> Self {
-        let cpu = AssembunnyCPU{};
-        let mut shell = VMShell::new(program.to_owned(), 0);
-        shell.vm.set_reg('a', a);
-        Self { cpu, shell }
-    }
-    /*
-    fn run(&mut self) {
-        self.shell.run(&self.cpu);
+    #[test]
+    fn day25_test() {
+        let input: Vec<Instruction> = test_input(include_str!("day25.testinput"));
+        assert_eq!(part1(&input), 198);
     }
-    */
-}
-
-fn part1(input: &[Instruction]) -> i64 {
-    let mut vm = AssembunnyVM::new(input, 0);
-    let _ = Debugger::run(&mut vm.shell, &vm.cpu);
-    vm.shell.vm.get_reg('a')
-}
-
-fn main() {
-    let input: Vec<Instruction> = read_input();
-    println!("Part 1: {}", part1(&input));
 }